@@ -18,6 +18,14 @@ pub trait ConsumerFilterConfig: Send + Sync + 'static {
 pub struct ConsumerConfig {
     #[serde(default)]
     pub name: String,
+    /// The groups whose filters and plugins are inherited by the consumer,
+    /// they are also used by the allow-lists of the routes.
+    ///
+    /// A plugin inherited from a group is replaced by the plugin of the same
+    /// type of a later group or of the consumer itself, e.g. the `limitCount`
+    /// of the consumer takes the place of the one of its group.
+    #[serde(default)]
+    pub groups: Vec<String>,
     #[serde(default)]
     pub filters: Vec<Box<dyn ConsumerFilterConfig>>,
     #[serde(default)]
@@ -25,3 +33,24 @@ pub struct ConsumerConfig {
     #[serde(default)]
     pub plugins: Vec<Box<dyn PluginConfig>>,
 }
+
+/// The filters and plugins shared by the consumers of the group.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsumerGroupConfig {
+    pub name: String,
+    #[serde(default)]
+    pub filters: Vec<Box<dyn ConsumerFilterConfig>>,
+    #[serde(default)]
+    pub plugins: Vec<Box<dyn PluginConfig>>,
+}
+
+/// The consumer of the requests without any credentials.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnonymousConfig {
+    #[serde(default)]
+    pub filters: Vec<Box<dyn ConsumerFilterConfig>>,
+    #[serde(default)]
+    pub plugins: Vec<Box<dyn PluginConfig>>,
+}
@@ -1,8 +1,17 @@
 use anyhow::Result;
 use poem::listener::BoxAcceptor;
 
+/// The scheme of the connections accepted by a listener.
+///
+/// It is inserted into the request extensions, because the URIs of the
+/// requests usually do not contain the scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenerScheme(pub &'static str);
+
 #[typetag::serde(tag = "type")]
 #[async_trait::async_trait]
 pub trait ListenerConfig: Send + Sync + 'static {
     async fn create(&self) -> Result<BoxAcceptor>;
+
+    fn scheme(&self) -> ListenerScheme;
 }
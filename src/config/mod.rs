@@ -6,33 +6,46 @@ mod plugin;
 mod provider;
 mod route;
 mod service;
+mod stream_route;
 
-use std::{cmp::Reverse, collections::HashMap, sync::Arc};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use poem::{
-    http::StatusCode,
-    listener::{AcceptorExt, BoxAcceptor},
-    Endpoint, IntoResponse, Request, Response, Route, Server,
+    http::StatusCode, listener::BoxAcceptor, Endpoint, IntoResponse, Request, Response, Route,
+    Server,
 };
 use serde::Deserialize;
 
 pub use crate::config::{
-    consumer::{ConsumerConfig, ConsumerFilterConfig},
-    listener::ListenerConfig,
+    consumer::{AnonymousConfig, ConsumerConfig, ConsumerFilterConfig, ConsumerGroupConfig},
+    listener::{ListenerConfig, ListenerScheme},
     plugin::{AuthPluginConfig, PluginConfig},
     provider::ConfigProvider,
-    route::RouteConfig,
+    route::{RouteAuth, RouteConfig},
     service::{ServiceConfig, ServiceTargetConfig},
+    stream_route::{StreamProtocol, StreamRouteConfig},
 };
 use crate::{
     consumer_filters::ConsumerFilter,
-    plugins::{AuthPlugin, NextPlugin, Plugin, PluginContext},
+    plugins::{
+        is_event_stream, is_streaming_response, with_idle_timeout, AuthPlugin, ConsumerName,
+        NextPlugin, Plugin, PluginContext,
+    },
+    stream_routes::StreamRoute,
 };
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Config {
+    /// Whether the requests without credentials are handled by the
+    /// anonymous consumer on the routes without `auth`, it takes effect only
+    /// if `anonymous` is specified or there are no consumers.
     #[serde(default = "default_allow_anonymous")]
     allow_anonymous: bool,
     #[serde(default)]
@@ -40,31 +53,57 @@ pub struct Config {
     #[serde(default)]
     pub consumers: Vec<ConsumerConfig>,
     #[serde(default)]
+    pub consumer_groups: Vec<ConsumerGroupConfig>,
+    /// The consumer of the requests without credentials, such as a stricter
+    /// rate limit.
+    #[serde(default)]
+    pub anonymous: Option<AnonymousConfig>,
+    #[serde(default)]
     pub routes: Vec<RouteConfig>,
     #[serde(default)]
     pub services: Vec<ServiceConfig>,
     #[serde(default)]
     pub global_plugins: Vec<Box<dyn PluginConfig>>,
+    #[serde(default)]
+    pub stream_routes: Vec<StreamRouteConfig>,
 }
 
 const fn default_allow_anonymous() -> bool {
     true
 }
 
-impl Config {
-    pub async fn create_server(&self) -> Result<Server<BoxAcceptor>> {
-        let mut iter = self.listeners.iter();
+/// The consumer name of the requests without credentials.
+const ANONYMOUS_CONSUMER: &str = "anonymous";
 
-        let mut acceptor = iter
-            .next()
-            .ok_or_else(|| anyhow!("At least one listener is required."))?
-            .create()
-            .await?;
+impl Config {
+    /// Creates a server for each listener, the scheme of the listener must
+    /// be inserted into the requests of its server.
+    pub async fn create_servers(&self) -> Result<Vec<(Server<BoxAcceptor>, ListenerScheme)>> {
+        anyhow::ensure!(
+            !self.listeners.is_empty(),
+            "At least one listener is required."
+        );
+
+        let mut servers = Vec::new();
+        for listener in &self.listeners {
+            servers.push((
+                Server::new_with_acceptor(listener.create().await?),
+                listener.scheme(),
+            ));
+        }
+        Ok(servers)
+    }
 
-        for listener in iter {
-            acceptor = acceptor.combine(listener.create().await?).boxed();
+    pub async fn create_stream_routes(&self) -> Result<Vec<StreamRoute>> {
+        let mut stream_routes = Vec::new();
+        for config in &self.stream_routes {
+            stream_routes.push(
+                StreamRoute::bind(config)
+                    .await
+                    .with_context(|| format!("Stream route `{}` is invalid.", config.bind))?,
+            );
         }
-        Ok(Server::new_with_acceptor(acceptor))
+        Ok(stream_routes)
     }
 
     pub async fn create_endpoint(&self) -> Result<Route> {
@@ -77,25 +116,107 @@ impl Config {
             global_plugins.push(plugin.create().await?);
         }
 
+        let mut groups = HashMap::new();
+        for group in &self.consumer_groups {
+            let mut filters = Vec::new();
+            let mut plugins = Vec::new();
+
+            for filter in &group.filters {
+                filters.push(filter.create()?);
+            }
+
+            for plugin in &group.plugins {
+                plugins.push(plugin.create().await?);
+            }
+
+            anyhow::ensure!(
+                groups
+                    .insert(group.name.as_str(), (filters, plugins))
+                    .is_none(),
+                "Consumer group `{}` is defined more than once.",
+                group.name
+            );
+        }
+
+        let mut consumer_names = HashSet::new();
         for consumer in &self.consumers {
-            let mut auth: Option<Arc<dyn AuthPlugin>> = None;
+            anyhow::ensure!(
+                consumer.name != ANONYMOUS_CONSUMER,
+                "Consumer name `{}` is reserved.",
+                ANONYMOUS_CONSUMER
+            );
+            anyhow::ensure!(
+                consumer_names.insert(consumer.name.as_str()),
+                "Consumer `{}` is defined more than once.",
+                consumer.name
+            );
+
+            let auth = match &consumer.auth {
+                Some(auth) => auth.create().await?,
+                None => {
+                    warn!(
+                        consumer = %consumer.name,
+                        "the consumer without auth plugin is ignored.",
+                    );
+                    continue;
+                }
+            };
+            let mut consumer_groups = Vec::new();
             let mut filters = Vec::new();
             let mut plugins = Vec::new();
 
-            if let Some(auth_plugin) = &consumer.auth {
-                auth = Some(auth_plugin.create().await?);
+            for name in &consumer.groups {
+                if consumer_groups.contains(name) {
+                    continue;
+                }
+                let (group_filters, group_plugins) = groups
+                    .get(name.as_str())
+                    .ok_or_else(|| anyhow!("Consumer group `{}` is not defined.", name))?;
+                consumer_groups.push(name.clone());
+                filters.extend(group_filters.iter().cloned());
+                override_plugins(&mut plugins, group_plugins.iter().cloned());
             }
 
             for filter in &consumer.filters {
                 filters.push(filter.create()?);
             }
 
+            let mut consumer_plugins = Vec::new();
             for plugin in &consumer.plugins {
-                plugins.push(plugin.create().await?);
+                consumer_plugins.push(plugin.create().await?);
+            }
+            override_plugins(&mut plugins, consumer_plugins);
+
+            consumers.push(Consumer {
+                name: consumer.name.clone(),
+                groups: consumer_groups,
+                auth: Some(auth),
+                filters,
+                plugins,
+            });
+        }
+
+        let mut anonymous_filters = Vec::new();
+        let mut anonymous_plugins = Vec::new();
+        if let Some(anonymous) = &self.anonymous {
+            for filter in &anonymous.filters {
+                anonymous_filters.push(filter.create()?);
             }
 
-            consumers.push((consumer.name.clone(), auth, filters, plugins));
+            for plugin in &anonymous.plugins {
+                anonymous_plugins.push(plugin.create().await?);
+            }
         }
+        let anonymous = Consumer {
+            name: ANONYMOUS_CONSUMER.to_string(),
+            groups: Vec::new(),
+            auth: None,
+            filters: anonymous_filters,
+            plugins: anonymous_plugins,
+        };
+        let credentials = Arc::new(CredentialIndex::new(&consumers)?);
+        let allow_anonymous =
+            self.allow_anonymous && (self.anonymous.is_some() || self.consumers.is_empty());
 
         for service in &self.services {
             let name = &service.name;
@@ -106,7 +227,7 @@ impl Config {
                 plugins.push(plugin.create().await?);
             }
 
-            services.insert(name, (ep, plugins));
+            services.insert(name, (ep, plugins, service.target.uses_plugin_context()));
         }
 
         for RouteConfig {
@@ -114,9 +235,13 @@ impl Config {
             strip,
             plugins,
             service,
+            auth: route_auth,
+            consumers: allowed_consumers,
+            consumer_groups: allowed_groups,
+            idle_stream_timeout,
         } in &self.routes
         {
-            let (service_ep, service_plugins) = services
+            let (service_ep, service_plugins, uses_plugin_context) = services
                 .get(&service)
                 .ok_or_else(|| anyhow!("Service `{}` is not defined.", service))?;
             let service_ep = service_ep.clone();
@@ -126,27 +251,81 @@ impl Config {
                 route_plugins.push(plugin.create().await?);
             }
 
-            let mut handlers = Vec::new();
-
-            if consumers.is_empty() && self.allow_anonymous {
-                consumers.push(Default::default());
+            for name in allowed_consumers {
+                anyhow::ensure!(
+                    self.consumers.iter().any(|consumer| &consumer.name == name),
+                    "Consumer `{}` is not defined.",
+                    name
+                );
+            }
+            for name in allowed_groups {
+                anyhow::ensure!(
+                    groups.contains_key(name.as_str()),
+                    "Consumer group `{}` is not defined.",
+                    name
+                );
             }
 
-            for (consumer_name, auth, filters, consumer_plugins) in consumers.clone() {
+            let make_handler = |consumer: &Consumer, allowed: bool| {
                 let mut plugins = Vec::new();
 
                 plugins.extend(service_plugins.clone());
                 plugins.extend(route_plugins.clone());
-                plugins.extend(consumer_plugins);
+                plugins.extend(consumer.plugins.clone());
                 plugins.extend(global_plugins.clone());
                 plugins.sort_by_key(|plugin| Reverse(plugin.priority()));
 
-                handlers.push((consumer_name, auth, filters, plugins));
-            }
+                Handler {
+                    consumer_name: consumer.name.clone(),
+                    auth: consumer.auth.clone(),
+                    filters: consumer.filters.clone(),
+                    plugins,
+                    allowed,
+                }
+            };
+
+            // the anonymous consumer is never in the allow-lists
+            let has_allow_list = !allowed_consumers.is_empty() || !allowed_groups.is_empty();
+            anyhow::ensure!(
+                !has_allow_list || matches!(route_auth, None | Some(RouteAuth::Required)),
+                "Route `{}` with `consumers` or `consumerGroups` requires `auth: required`.",
+                path
+            );
+            let route_auth = route_auth.unwrap_or(if allow_anonymous && !has_allow_list {
+                RouteAuth::Optional
+            } else {
+                RouteAuth::Required
+            });
+            let handlers = match route_auth {
+                RouteAuth::None => Vec::new(),
+                RouteAuth::Required | RouteAuth::Optional => consumers
+                    .iter()
+                    .map(|consumer| {
+                        let allowed = !has_allow_list
+                            || allowed_consumers.contains(&consumer.name)
+                            || consumer
+                                .groups
+                                .iter()
+                                .any(|group| allowed_groups.contains(group));
+                        make_handler(consumer, allowed)
+                    })
+                    .collect(),
+            };
+            let anonymous = match route_auth {
+                RouteAuth::Required => None,
+                RouteAuth::Optional | RouteAuth::None => Some(make_handler(&anonymous, true)),
+            };
 
             let ep = RouteEndpoint {
                 handlers,
+                credentials: match route_auth {
+                    RouteAuth::None => Default::default(),
+                    RouteAuth::Required | RouteAuth::Optional => credentials.clone(),
+                },
+                anonymous,
                 endpoint: service_ep.clone(),
+                uses_plugin_context: *uses_plugin_context,
+                idle_stream_timeout: idle_stream_timeout.map(Duration::from_secs),
             };
 
             if *strip {
@@ -160,18 +339,136 @@ impl Config {
     }
 }
 
+/// Appends the plugins of an inherited level, which replace the plugins of
+/// the same types of the previous levels.
+fn override_plugins(
+    plugins: &mut Vec<Arc<dyn Plugin>>,
+    overrides: impl IntoIterator<Item = Arc<dyn Plugin>>,
+) {
+    let overrides = overrides.into_iter().collect::<Vec<_>>();
+    plugins.retain(|plugin| {
+        overrides
+            .iter()
+            .all(|override_plugin| override_plugin.name() != plugin.name())
+    });
+    plugins.extend(overrides);
+}
+
+struct Consumer {
+    name: String,
+    groups: Vec<String>,
+    /// `None` for the anonymous consumer.
+    auth: Option<Arc<dyn AuthPlugin>>,
+    filters: Vec<Arc<dyn ConsumerFilter>>,
+    plugins: Vec<Arc<dyn Plugin>>,
+}
+
+/// The result of looking up the consumer of a request.
+enum Lookup {
+    /// The request does not carry any credentials.
+    NoCredentials,
+    /// The credentials do not belong to any consumer.
+    Unknown,
+    /// The index of the consumer whose credentials match the identifier.
+    Consumer(usize),
+}
+
+/// Finds the consumers by the identifiers of their credentials, so only the
+/// auth plugin of the matched consumer verifies the request.
+#[derive(Default)]
+struct CredentialIndex {
+    /// A plugin of each kind of credentials, used to extract the identifiers.
+    extractors: Vec<Arc<dyn AuthPlugin>>,
+    consumers: HashMap<(&'static str, String), usize>,
+}
+
+impl CredentialIndex {
+    fn new(consumers: &[Consumer]) -> Result<Self> {
+        let mut index = CredentialIndex::default();
+
+        for (idx, consumer) in consumers.iter().enumerate() {
+            let auth = match &consumer.auth {
+                Some(auth) => auth,
+                None => continue,
+            };
+            if !index
+                .extractors
+                .iter()
+                .any(|extractor| extractor.kind() == auth.kind())
+            {
+                index.extractors.push(auth.clone());
+            }
+            if let Some(existing) = index
+                .consumers
+                .insert((auth.kind(), auth.credential_id().to_string()), idx)
+            {
+                bail!(
+                    "Consumer `{}` and `{}` have the same `{}` credentials.",
+                    consumers[existing].name,
+                    consumer.name,
+                    auth.kind()
+                );
+            }
+        }
+
+        Ok(index)
+    }
+
+    fn lookup(&self, req: &Request) -> Lookup {
+        let mut lookup = Lookup::NoCredentials;
+        for extractor in &self.extractors {
+            if let Some(id) = extractor.extract(req) {
+                match self.consumers.get(&(extractor.kind(), id)) {
+                    Some(idx) => return Lookup::Consumer(*idx),
+                    None => lookup = Lookup::Unknown,
+                }
+            }
+        }
+        lookup
+    }
+}
+
 fn check_consumer(filters: &[Arc<dyn ConsumerFilter>], req: &Request) -> bool {
     filters.iter().all(|filter| filter.check(req))
 }
 
+struct Handler {
+    consumer_name: String,
+    /// `None` for the anonymous consumer.
+    auth: Option<Arc<dyn AuthPlugin>>,
+    filters: Vec<Arc<dyn ConsumerFilter>>,
+    plugins: Vec<Arc<dyn Plugin>>,
+    /// Whether the consumer is in the allow-lists of the route.
+    allowed: bool,
+}
+
 struct RouteEndpoint {
-    handlers: Vec<(
-        String,
-        Option<Arc<dyn AuthPlugin>>,
-        Vec<Arc<dyn ConsumerFilter>>,
-        Vec<Arc<dyn Plugin>>,
-    )>,
+    /// The handlers of the consumers, in the order of the credential index.
+    handlers: Vec<Handler>,
+    credentials: Arc<CredentialIndex>,
+    anonymous: Option<Handler>,
     endpoint: Arc<dyn Endpoint<Output = Response>>,
+    uses_plugin_context: bool,
+    idle_stream_timeout: Option<Duration>,
+}
+
+impl RouteEndpoint {
+    async fn dispatch(&self, handler: &Handler, mut req: Request) -> Response {
+        let mut ctx = PluginContext::new(&req);
+        ctx.insert("consumerName", &handler.consumer_name);
+        req.extensions_mut()
+            .insert(ConsumerName(handler.consumer_name.clone()));
+        let next = NextPlugin::new(&handler.plugins, &self.endpoint, self.uses_plugin_context);
+        let mut resp = next.call(&mut ctx, req).await;
+        if let Some(idle_timeout) = self.idle_stream_timeout {
+            if is_streaming_response(&resp) {
+                let close_cleanly = is_event_stream(&resp);
+                let body = resp.take_body();
+                resp.set_body(with_idle_timeout(body, idle_timeout, close_cleanly));
+            }
+        }
+        resp
+    }
 }
 
 #[async_trait::async_trait]
@@ -179,14 +476,27 @@ impl Endpoint for RouteEndpoint {
     type Output = Response;
 
     async fn call(&self, req: Request) -> Self::Output {
-        for (consumer_name, auth, filter, plugins) in &self.handlers {
-            if let Some(auth) = auth {
-                if auth.auth(&req).await {
-                    if check_consumer(filter, &req) {
-                        let mut ctx = PluginContext::new(&req);
-                        ctx.insert("consumerName", consumer_name);
-                        let next = NextPlugin::new(plugins, &self.endpoint);
-                        return next.call(&mut ctx, req).await;
+        match self.credentials.lookup(&req) {
+            Lookup::Consumer(idx) => {
+                let handler = &self.handlers[idx];
+                let verified = match &handler.auth {
+                    Some(auth) => auth.verify(&req).await,
+                    None => false,
+                };
+                if verified && check_consumer(&handler.filters, &req) {
+                    if !handler.allowed {
+                        return StatusCode::FORBIDDEN.into_response();
+                    }
+                    return self.dispatch(handler, req).await;
+                }
+            }
+            // the requests with invalid credentials are rejected instead of
+            // being handled as anonymous
+            Lookup::Unknown => {}
+            Lookup::NoCredentials => {
+                if let Some(anonymous) = &self.anonymous {
+                    if check_consumer(&anonymous.filters, &req) {
+                        return self.dispatch(anonymous, req).await;
                     }
                 }
             }
@@ -2,6 +2,20 @@ use serde::{Deserialize, Serialize};
 
 use crate::config::PluginConfig;
 
+/// How the consumers of the requests are authenticated.
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum RouteAuth {
+    /// Only the authenticated consumers are allowed.
+    Required,
+    /// The requests without credentials are handled by the anonymous
+    /// consumer.
+    Optional,
+    /// The credentials are not checked, all the requests are handled by the
+    /// anonymous consumer.
+    None,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RouteConfig {
@@ -11,4 +25,19 @@ pub struct RouteConfig {
     #[serde(default)]
     pub plugins: Vec<Box<dyn PluginConfig>>,
     pub service: String,
+    /// Defaults to `optional` if anonymous requests are allowed by the
+    /// config, otherwise `required`.
+    #[serde(default)]
+    pub auth: Option<RouteAuth>,
+    /// The consumers allowed to access the route, in addition to the
+    /// members of `consumerGroups`. All the consumers are allowed if both
+    /// are empty, otherwise `auth` must be `required`.
+    #[serde(default)]
+    pub consumers: Vec<String>,
+    #[serde(default)]
+    pub consumer_groups: Vec<String>,
+    /// Closes the streaming responses, such as Server-Sent Events, if no
+    /// data is sent for this number of seconds.
+    #[serde(default)]
+    pub idle_stream_timeout: Option<u64>,
 }
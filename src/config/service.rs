@@ -18,4 +18,10 @@ pub struct ServiceConfig {
 #[typetag::serde(tag = "type")]
 pub trait ServiceTargetConfig: Send + Sync + 'static {
     fn create(&self) -> Result<Arc<dyn Endpoint<Output = Response>>>;
+
+    /// Returns `true` if the target reads the `PluginContext` from the
+    /// request extensions.
+    fn uses_plugin_context(&self) -> bool {
+        false
+    }
 }
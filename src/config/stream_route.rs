@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::ConsumerFilterConfig,
+    discovery::DiscoveryConfig,
+    service_targets::upstream::{
+        balancer::BalancerConfig, default_balancer, dns::DnsConfig, node::NodeConfig,
+    },
+};
+
+#[derive(Serialize, Deserialize, Copy, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum StreamProtocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+/// Proxies the raw TCP connections or UDP datagrams received by its own
+/// listener to the upstream nodes.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamRouteConfig {
+    #[serde(default)]
+    pub name: String,
+    pub bind: String,
+    #[serde(default)]
+    pub protocol: StreamProtocol,
+    /// The clients must pass all the filters.
+    #[serde(default)]
+    pub filters: Vec<Box<dyn ConsumerFilterConfig>>,
+    /// The nodes must have ports.
+    #[serde(default)]
+    pub nodes: Vec<NodeConfig>,
+    #[serde(default)]
+    pub dns: Option<DnsConfig>,
+    #[serde(default)]
+    pub discovery: Option<Box<dyn DiscoveryConfig>>,
+    #[serde(default = "default_balancer")]
+    pub balancer: Box<dyn BalancerConfig>,
+    #[serde(default)]
+    pub connect_timeout_ms: Option<u64>,
+    /// The number of nodes to try to connect to for each TCP connection.
+    #[serde(default = "default_connect_attempts")]
+    pub connect_attempts: usize,
+    /// The connection or UDP session is closed if there is no traffic for
+    /// this number of seconds.
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout: u64,
+    /// The maximum number of UDP sessions, the datagrams of the new clients
+    /// are dropped when it is reached.
+    #[serde(default = "default_max_sessions")]
+    pub max_sessions: usize,
+}
+
+const fn default_connect_attempts() -> usize {
+    1
+}
+
+const fn default_idle_timeout() -> u64 {
+    600
+}
+
+const fn default_max_sessions() -> usize {
+    4096
+}
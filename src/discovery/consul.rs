@@ -0,0 +1,222 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use futures_util::stream::BoxStream;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::discovery::{format_host, DiscoveredNode, DiscoveryConfig};
+
+/// Watches the healthy instances of a service in the Consul catalog with
+/// blocking queries.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConsulDiscoveryConfig {
+    service_name: String,
+    /// The address of the Consul agent.
+    #[serde(default = "default_address")]
+    address: String,
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    datacenter: Option<String>,
+    /// Only the instances with this tag are used.
+    #[serde(default)]
+    tag: Option<String>,
+    /// Only the instances whose health checks are all passing are used,
+    /// otherwise the instances with critical checks are excluded.
+    #[serde(default = "default_passing_only")]
+    passing_only: bool,
+    /// The maximum duration in seconds of a blocking query.
+    #[serde(default = "default_wait")]
+    wait: u64,
+    /// The delay in seconds before retrying a failed query.
+    #[serde(default = "default_retry_interval")]
+    retry_interval: u64,
+}
+
+fn default_address() -> String {
+    "http://127.0.0.1:8500".to_string()
+}
+
+const fn default_passing_only() -> bool {
+    true
+}
+
+const fn default_wait() -> u64 {
+    300
+}
+
+const fn default_retry_interval() -> u64 {
+    5
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ServiceEntry {
+    node: CatalogNode,
+    service: AgentService,
+    #[serde(default)]
+    checks: Vec<HealthCheck>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CatalogNode {
+    address: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AgentService {
+    #[serde(default)]
+    address: String,
+    port: u16,
+    #[serde(default)]
+    weights: Option<Weights>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Weights {
+    passing: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HealthCheck {
+    status: String,
+}
+
+impl ServiceEntry {
+    fn into_node(self) -> Option<DiscoveredNode> {
+        if self.checks.iter().any(|check| check.status == "critical") {
+            return None;
+        }
+        let host = if self.service.address.is_empty() {
+            &self.node.address
+        } else {
+            &self.service.address
+        };
+        Some(DiscoveredNode {
+            host: format_host(host, self.service.port),
+            weight: self
+                .service
+                .weights
+                .map(|weights| weights.passing)
+                .unwrap_or(1),
+        })
+    }
+}
+
+struct ConsulWatcher {
+    client: Client,
+    url: String,
+    query: Vec<(&'static str, String)>,
+    token: Option<String>,
+}
+
+impl ConsulWatcher {
+    /// Sends a blocking query, returns the nodes and the index of the result.
+    async fn query(&self, index: u64) -> Result<(Vec<DiscoveredNode>, u64)> {
+        let mut req = self
+            .client
+            .get(&self.url)
+            .query(&self.query)
+            .query(&[("index", index)]);
+        if let Some(token) = &self.token {
+            req = req.header("X-Consul-Token", token);
+        }
+
+        let resp = req.send().await?.error_for_status()?;
+        let new_index = resp
+            .headers()
+            .get("X-Consul-Index")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .ok_or_else(|| anyhow!("missing `X-Consul-Index` header"))?;
+        let entries: Vec<ServiceEntry> = serde_json::from_slice(&resp.bytes().await?)?;
+
+        let mut nodes = entries
+            .into_iter()
+            .filter_map(ServiceEntry::into_node)
+            .filter(|node| node.weight > 0)
+            .collect::<Vec<_>>();
+        nodes.sort_by(|a, b| a.host.cmp(&b.host));
+        Ok((nodes, new_index))
+    }
+}
+
+#[typetag::serde(name = "consul")]
+impl DiscoveryConfig for ConsulDiscoveryConfig {
+    fn watch(&self) -> Result<BoxStream<'static, Vec<DiscoveredNode>>> {
+        anyhow::ensure!(
+            self.retry_interval > 0,
+            "the retry interval must be greater than 0"
+        );
+
+        let wait = Duration::from_secs(self.wait);
+        // Consul adds a jitter of up to `wait / 16` to blocking queries
+        let client = Client::builder()
+            .timeout(wait + wait / 16 + Duration::from_secs(5))
+            .build()
+            .context("failed to create the consul client")?;
+
+        let mut query = vec![("wait", format!("{}s", self.wait))];
+        if self.passing_only {
+            query.push(("passing", "true".to_string()));
+        }
+        if let Some(datacenter) = &self.datacenter {
+            query.push(("dc", datacenter.clone()));
+        }
+        if let Some(tag) = &self.tag {
+            query.push(("tag", tag.clone()));
+        }
+
+        let watcher = ConsulWatcher {
+            client,
+            url: format!(
+                "{}/v1/health/service/{}",
+                self.address.trim_end_matches('/'),
+                percent_encoding::utf8_percent_encode(
+                    &self.service_name,
+                    percent_encoding::NON_ALPHANUMERIC
+                )
+            ),
+            query,
+            token: self.token.clone(),
+        };
+        let service_name = self.service_name.clone();
+        let retry_interval = Duration::from_secs(self.retry_interval);
+
+        Ok(Box::pin(async_stream::stream! {
+            let mut index = 0;
+            let mut current_nodes: Option<Vec<DiscoveredNode>> = None;
+
+            info!(url = %watcher.url, service = %service_name, "watch the consul service.");
+
+            loop {
+                match watcher.query(index).await {
+                    Ok((nodes, new_index)) => {
+                        // the index must be reset if it goes backwards, and it
+                        // must be at least 1, otherwise the queries do not block
+                        index = if new_index < index { 1 } else { new_index.max(1) };
+                        if current_nodes.as_ref() != Some(&nodes) {
+                            current_nodes = Some(nodes.clone());
+                            yield nodes;
+                        }
+                    }
+                    Err(err) => {
+                        // keep the last known nodes
+                        warn!(
+                            service = %service_name,
+                            error = %err,
+                            "failed to query the consul service.",
+                        );
+                        tokio::time::sleep(retry_interval).await;
+                    }
+                }
+            }
+        }))
+    }
+}
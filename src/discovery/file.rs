@@ -0,0 +1,97 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result};
+use futures_util::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+
+use crate::discovery::{DiscoveredNode, DiscoveryConfig};
+
+/// Reads the nodes from a JSON or YAML file that maps the service names to
+/// the nodes, the file is reloaded when it changes.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FileDiscoveryConfig {
+    service_name: String,
+    path: PathBuf,
+    /// The interval in seconds of checking the file for changes.
+    #[serde(default = "default_interval")]
+    interval: u64,
+}
+
+const fn default_interval() -> u64 {
+    5
+}
+
+fn parse(path: &std::path::Path, data: &str) -> Result<HashMap<String, Vec<DiscoveredNode>>> {
+    let is_json = path
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("json"))
+        .unwrap_or_default();
+    if is_json {
+        Ok(serde_json::from_str(data)?)
+    } else {
+        Ok(serde_yaml::from_str(data)?)
+    }
+}
+
+#[typetag::serde(name = "file")]
+impl DiscoveryConfig for FileDiscoveryConfig {
+    fn watch(&self) -> Result<BoxStream<'static, Vec<DiscoveredNode>>> {
+        anyhow::ensure!(self.interval > 0, "the interval must be greater than 0");
+
+        let service_name = self.service_name.clone();
+        let path = self.path.clone();
+        let interval = Duration::from_secs(self.interval);
+
+        Ok(Box::pin(async_stream::stream! {
+            let mut current_data: Option<String> = None;
+            let mut current_nodes: Option<Vec<DiscoveredNode>> = None;
+
+            info!(path = %path.display(), service = %service_name, "watch the discovery file.");
+
+            loop {
+                match tokio::fs::read_to_string(&path).await {
+                    Ok(data) if current_data.as_ref() != Some(&data) => {
+                        let res = parse(&path, &data).with_context(|| {
+                            format!("failed to parse the discovery file `{}`", path.display())
+                        });
+                        current_data = Some(data);
+
+                        match res {
+                            Ok(mut services) => {
+                                let nodes = services.remove(&service_name).unwrap_or_else(|| {
+                                    warn!(
+                                        path = %path.display(),
+                                        service = %service_name,
+                                        "service not found in the discovery file.",
+                                    );
+                                    Vec::new()
+                                });
+                                if current_nodes.as_ref() != Some(&nodes) {
+                                    current_nodes = Some(nodes.clone());
+                                    yield nodes;
+                                }
+                            }
+                            Err(err) => {
+                                error!(
+                                    error = %format!("{:#}", err),
+                                    "invalid discovery file.",
+                                );
+                            }
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        warn!(
+                            path = %path.display(),
+                            error = %err,
+                            "failed to read the discovery file.",
+                        );
+                    }
+                }
+
+                tokio::time::sleep(interval).await;
+            }
+        }))
+    }
+}
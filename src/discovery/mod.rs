@@ -0,0 +1,36 @@
+mod consul;
+mod file;
+
+use anyhow::Result;
+use futures_util::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+
+/// A node of a service found by the discovery.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscoveredNode {
+    /// The address of the node, `host:port`.
+    pub host: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+const fn default_weight() -> u32 {
+    1
+}
+
+#[typetag::serde(tag = "type")]
+pub trait DiscoveryConfig: Send + Sync + 'static {
+    /// Watches the nodes of the service, the stream yields the whole list of
+    /// nodes each time they change.
+    fn watch(&self) -> Result<BoxStream<'static, Vec<DiscoveredNode>>>;
+}
+
+/// Formats `host:port`, with the brackets of IPv6 addresses.
+fn format_host(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
@@ -1,7 +1,7 @@
 use poem::listener::{AcceptorExt, BoxAcceptor, Listener as _};
 use serde::{Deserialize, Serialize};
 
-use crate::config::ListenerConfig;
+use crate::config::{ListenerConfig, ListenerScheme};
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -23,4 +23,8 @@ impl ListenerConfig for TcpListener {
             .await?
             .boxed())
     }
+
+    fn scheme(&self) -> ListenerScheme {
+        ListenerScheme("http")
+    }
 }
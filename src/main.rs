@@ -5,17 +5,23 @@ extern crate anyhow;
 
 mod config;
 mod consumer_filters;
+mod discovery;
 mod listeners;
 mod plugins;
 mod service_targets;
+mod stream_routes;
 
-use std::{path::PathBuf, time::Duration};
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
+use poem::{middleware::AddData, EndpointExt};
 use structopt::StructOpt;
 use tokio::task::JoinHandle;
 use tokio_stream::StreamExt;
 
-use crate::config::{providers::FileProvider, ConfigProvider};
+use crate::{
+    config::{providers::FileProvider, ConfigProvider},
+    stream_routes::StreamRoute,
+};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "poem-gateway")]
@@ -53,25 +59,42 @@ async fn main() {
             tokio::time::sleep(Duration::from_secs(1)).await;
         }
 
-        let server = match cfg.create_server().await {
-            Ok(server) => server,
+        let servers = match cfg.create_servers().await {
+            Ok(servers) => servers,
             Err(err) => {
                 error!(error = %err, "failed to create the server.");
                 continue;
             }
         };
         let ep = match cfg.create_endpoint().await {
-            Ok(ep) => ep,
+            Ok(ep) => Arc::new(ep),
             Err(err) => {
                 error!(error = %err, "failed to initialize the server.");
                 continue;
             }
         };
 
-        let handle = tokio::spawn(async move {
-            if let Err(err) = server.run(ep).await {
-                error!(error = %err, "server error");
+        let stream_routes = match cfg.create_stream_routes().await {
+            Ok(stream_routes) => stream_routes,
+            Err(err) => {
+                error!(error = %format!("{:#}", err), "failed to create the stream routes.");
+                continue;
             }
+        };
+
+        let handle = tokio::spawn(async move {
+            let servers =
+                futures_util::future::join_all(servers.into_iter().map(|(server, scheme)| {
+                    let ep = ep.clone().with(AddData::new(scheme));
+                    async move {
+                        if let Err(err) = server.run(ep).await {
+                            error!(error = %err, "server error");
+                        }
+                    }
+                }));
+            let stream_routes =
+                futures_util::future::join_all(stream_routes.into_iter().map(StreamRoute::run));
+            futures_util::future::join(servers, stream_routes).await;
         });
         current_server_handle = Some(handle);
     }
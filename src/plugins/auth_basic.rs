@@ -37,7 +37,21 @@ struct BasicAuth {
 
 #[async_trait::async_trait]
 impl AuthPlugin for BasicAuth {
-    async fn auth(&self, req: &Request) -> bool {
+    fn kind(&self) -> &'static str {
+        "basic"
+    }
+
+    fn credential_id(&self) -> &str {
+        &self.username
+    }
+
+    fn extract(&self, req: &Request) -> Option<String> {
+        req.headers()
+            .typed_get::<headers::Authorization<Basic>>()
+            .map(|auth| auth.0.username().to_string())
+    }
+
+    async fn verify(&self, req: &Request) -> bool {
         if let Some(auth) = req.headers().typed_get::<headers::Authorization<Basic>>() {
             if self.username == auth.0.username() && self.password == auth.0.password() {
                 return true;
@@ -11,7 +11,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     config::PluginConfig,
-    plugins::{NextPlugin, Plugin, PluginContext},
+    plugins::{is_streaming_response, NextPlugin, Plugin, PluginContext},
 };
 
 #[derive(Serialize, Deserialize)]
@@ -108,6 +108,10 @@ impl<T> Plugin for CircuitBreaker<T>
 where
     T: failsafe::futures::CircuitBreaker + Send + Sync + 'static,
 {
+    fn name(&self) -> &'static str {
+        "circuitBreaker"
+    }
+
     fn priority(&self) -> i32 {
         100
     }
@@ -132,6 +136,13 @@ where
             Err(failsafe::Error::Inner(mut resp)) => {
                 let status = resp.status();
                 let headers = resp.headers().clone();
+
+                // the streams are not buffered, they are replayed without the body
+                if is_streaming_response(&resp) {
+                    *self.last_err_resp.write() = Some((status, headers, Bytes::new()));
+                    return resp;
+                }
+
                 let body = resp.take_body().into_bytes().await.ok().unwrap_or_default();
 
                 *self.last_err_resp.write() = Some((status, headers, body.clone()));
@@ -0,0 +1,501 @@
+mod path_template;
+
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use hyper::body::HttpBody;
+use percent_encoding::percent_decode_str;
+use poem::{
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri},
+    Request, Response,
+};
+use prost::Message;
+use prost_reflect::{
+    DescriptorPool, DynamicMessage, Kind, MessageDescriptor, MethodDescriptor, Value,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map};
+
+use crate::{
+    config::PluginConfig,
+    plugins::{grpc_transcode::path_template::PathTemplate, NextPlugin, Plugin, PluginContext},
+};
+
+const GRPC_STATUS: &str = "grpc-status";
+const GRPC_MESSAGE: &str = "grpc-message";
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RuleConfig {
+    /// The gRPC method, such as `helloworld.Greeter/SayHello`.
+    method: String,
+    #[serde(default = "default_http_method")]
+    http_method: String,
+    path: String,
+    /// The request field that the body is mapped to, `*` for the whole
+    /// request message.
+    #[serde(default)]
+    body: Option<String>,
+    /// The response field that is returned as the body instead of the whole
+    /// response message.
+    #[serde(default)]
+    response_body: Option<String>,
+}
+
+fn default_http_method() -> String {
+    "POST".to_string()
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Config {
+    /// The file of the `FileDescriptorSet`, generated by `protoc
+    /// --include_imports --descriptor_set_out`.
+    descriptor_file: String,
+    /// The mapping rules in addition to the `google.api.http` options in the
+    /// descriptor set.
+    #[serde(default)]
+    rules: Vec<RuleConfig>,
+    /// The maximum size of the request body and of the gRPC response.
+    #[serde(default = "default_max_body_size")]
+    max_body_size: usize,
+}
+
+const fn default_max_body_size() -> usize {
+    4 * 1024 * 1024
+}
+
+#[typetag::serde(name = "grpcTranscode")]
+#[async_trait::async_trait]
+impl PluginConfig for Config {
+    async fn create(&self) -> Result<Arc<dyn Plugin>> {
+        let data = tokio::fs::read(&self.descriptor_file)
+            .await
+            .with_context(|| format!("failed to read file `{}`", self.descriptor_file))?;
+        let pool = DescriptorPool::decode(data.as_slice())
+            .with_context(|| format!("invalid descriptor set file `{}`", self.descriptor_file))?;
+
+        let mut rules = Vec::new();
+        for rule in self.rules.iter().chain(&annotated_rules(&pool)) {
+            rules.push(
+                rule.create(&pool)
+                    .with_context(|| format!("invalid rule for method `{}`", rule.method))?,
+            );
+        }
+        anyhow::ensure!(
+            !rules.is_empty(),
+            "no transcoding rules in `{}`",
+            self.descriptor_file
+        );
+
+        Ok(Arc::new(GrpcTranscode {
+            rules,
+            max_body_size: self.max_body_size,
+        }))
+    }
+}
+
+/// Returns the rules of the `google.api.http` options in `pool`.
+fn annotated_rules(pool: &DescriptorPool) -> Vec<RuleConfig> {
+    let http_ext = match pool.get_extension_by_name("google.api.http") {
+        Some(http_ext) => http_ext,
+        None => return Vec::new(),
+    };
+    let mut rules = Vec::new();
+
+    for service in pool.services() {
+        for method in service.methods() {
+            let options = method.options();
+            if !options.has_extension(&http_ext) {
+                continue;
+            }
+            if let Value::Message(http_rule) = &*options.get_extension(&http_ext) {
+                let name = format!("{}/{}", service.full_name(), method.name());
+                rules.extend(http_rule_configs(&name, http_rule));
+            }
+        }
+    }
+
+    rules
+}
+
+fn http_rule_configs(method: &str, http_rule: &DynamicMessage) -> Vec<RuleConfig> {
+    let string_field = |msg: &DynamicMessage, name: &str| {
+        msg.get_field_by_name(name)
+            .and_then(|value| value.as_str().map(ToString::to_string))
+            .filter(|value| !value.is_empty())
+    };
+
+    let pattern = ["get", "put", "post", "delete", "patch"]
+        .iter()
+        .find_map(|name| string_field(http_rule, name).map(|path| (name.to_uppercase(), path)))
+        .or_else(|| match http_rule.get_field_by_name("custom").as_deref() {
+            Some(Value::Message(custom)) => {
+                Some((string_field(custom, "kind")?, string_field(custom, "path")?))
+            }
+            _ => None,
+        });
+
+    let mut rules = Vec::new();
+    if let Some((http_method, path)) = pattern {
+        rules.push(RuleConfig {
+            method: method.to_string(),
+            http_method,
+            path,
+            body: string_field(http_rule, "body"),
+            response_body: string_field(http_rule, "response_body"),
+        });
+    }
+    if let Some(Value::List(bindings)) = http_rule
+        .get_field_by_name("additional_bindings")
+        .as_deref()
+    {
+        for binding in bindings {
+            if let Value::Message(binding) = binding {
+                rules.extend(http_rule_configs(method, binding));
+            }
+        }
+    }
+    rules
+}
+
+impl RuleConfig {
+    fn create(&self, pool: &DescriptorPool) -> Result<Rule> {
+        let (service_name, method_name) = self
+            .method
+            .split_once('/')
+            .ok_or_else(|| anyhow!("method must be in the form of `package.Service/Method`"))?;
+        let method = pool
+            .get_service_by_name(service_name)
+            .and_then(|service| {
+                service
+                    .methods()
+                    .find(|method| method.name() == method_name)
+            })
+            .ok_or_else(|| anyhow!("method not found in the descriptor set"))?;
+        anyhow::ensure!(
+            !method.is_client_streaming(),
+            "client streaming methods cannot be transcoded"
+        );
+
+        if let Some(body) = self.body.as_deref().filter(|body| *body != "*") {
+            anyhow::ensure!(
+                method.input().get_field_by_name(body).is_some(),
+                "body field `{}` not found",
+                body
+            );
+        }
+        let response_body = match &self.response_body {
+            Some(response_body) => Some(
+                method
+                    .output()
+                    .get_field_by_name(response_body)
+                    .ok_or_else(|| anyhow!("response body field `{}` not found", response_body))?
+                    .json_name()
+                    .to_string(),
+            ),
+            None => None,
+        };
+
+        Ok(Rule {
+            http_method: self
+                .http_method
+                .parse()
+                .with_context(|| format!("invalid http method `{}`", self.http_method))?,
+            template: PathTemplate::parse(&self.path)?,
+            grpc_path: format!("/{}/{}", method.parent_service().full_name(), method.name())
+                .parse()?,
+            method,
+            body: self.body.clone().filter(|body| !body.is_empty()),
+            response_body,
+        })
+    }
+}
+
+struct Rule {
+    http_method: Method,
+    template: PathTemplate,
+    method: MethodDescriptor,
+    grpc_path: Uri,
+    body: Option<String>,
+    response_body: Option<String>,
+}
+
+struct GrpcTranscode {
+    rules: Vec<Rule>,
+    max_body_size: usize,
+}
+
+#[async_trait::async_trait]
+impl Plugin for GrpcTranscode {
+    fn name(&self) -> &'static str {
+        "grpcTranscode"
+    }
+
+    fn priority(&self) -> i32 {
+        -10
+    }
+
+    async fn call(
+        &self,
+        mut req: Request,
+        ctx: &mut PluginContext,
+        next: NextPlugin<'_>,
+    ) -> Response {
+        let (rule, variables) = match self.rules.iter().find_map(|rule| {
+            if rule.http_method != req.method() {
+                return None;
+            }
+            rule.template
+                .matches(req.uri().path())
+                .map(|variables| (rule, variables))
+        }) {
+            Some(res) => res,
+            None => return next.call(ctx, req).await,
+        };
+
+        let message = match self.request_message(rule, variables, &mut req).await {
+            Ok(message) => message,
+            Err(err) => return error_response(StatusCode::BAD_REQUEST, 3, &format!("{:#}", err)),
+        };
+
+        let mut frame = BytesMut::with_capacity(message.encoded_len() + 5);
+        frame.put_u8(0);
+        frame.put_u32(message.encoded_len() as u32);
+        message
+            .encode(&mut frame)
+            .expect("the buffer has enough capacity");
+
+        req.set_method(Method::POST);
+        *req.uri_mut() = rule.grpc_path.clone();
+        let headers = req.headers_mut();
+        headers.remove(header::CONTENT_LENGTH);
+        headers.remove(header::ACCEPT_ENCODING);
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/grpc"),
+        );
+        headers.insert(header::TE, HeaderValue::from_static("trailers"));
+        req.set_body(frame.freeze());
+
+        let resp = next.call(ctx, req).await;
+        transcode_response(rule, resp, self.max_body_size).await
+    }
+}
+
+impl GrpcTranscode {
+    async fn request_message(
+        &self,
+        rule: &Rule,
+        variables: Vec<(&str, String)>,
+        req: &mut Request,
+    ) -> Result<DynamicMessage> {
+        let desc = rule.method.input();
+        let mut fields = Map::new();
+
+        if let Some(body) = &rule.body {
+            let data = read_body(req.take_body().into(), self.max_body_size)
+                .await
+                .context("failed to read the request body")?
+                .0;
+            if !data.is_empty() {
+                let value: serde_json::Value =
+                    serde_json::from_slice(&data).context("invalid json body")?;
+                if body == "*" {
+                    match value {
+                        serde_json::Value::Object(obj) => fields = obj,
+                        _ => bail!("the request body must be a json object"),
+                    }
+                } else {
+                    fields.insert(body.clone(), value);
+                }
+            }
+        }
+
+        for (field, value) in variables {
+            set_field(&mut fields, &desc, field, value)?;
+        }
+
+        // the query parameters are only mapped if the body is not the whole
+        // request message
+        if rule.body.as_deref() != Some("*") {
+            if let Some(query) = req.uri().query() {
+                for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+                    let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    let decode = |s: &str| {
+                        percent_decode_str(&s.replace('+', " "))
+                            .decode_utf8_lossy()
+                            .into_owned()
+                    };
+                    set_field(&mut fields, &desc, &decode(name), decode(value))?;
+                }
+            }
+        }
+
+        Ok(DynamicMessage::deserialize(
+            desc,
+            serde_json::Value::Object(fields),
+        )?)
+    }
+}
+
+/// Sets the field at the dotted `path` of the json object that is
+/// deserialized to a message of `desc`.
+fn set_field(
+    fields: &mut Map<String, serde_json::Value>,
+    desc: &MessageDescriptor,
+    path: &str,
+    value: String,
+) -> Result<()> {
+    let (name, rest) = match path.split_once('.') {
+        Some((name, rest)) => (name, Some(rest)),
+        None => (path, None),
+    };
+    let field = desc
+        .get_field_by_name(name)
+        .or_else(|| desc.get_field_by_json_name(name))
+        .ok_or_else(|| anyhow!("unknown field `{}`", path))?;
+
+    match (field.kind(), rest) {
+        (Kind::Message(desc), Some(rest)) if !field.is_list() => {
+            let entry = fields
+                .entry(field.name().to_string())
+                .or_insert_with(|| json!({}));
+            match entry {
+                serde_json::Value::Object(fields) => set_field(fields, &desc, rest, value),
+                _ => bail!("invalid field `{}`", path),
+            }
+        }
+        (_, Some(_)) => bail!("field `{}` is not a message", name),
+        (kind, None) => {
+            let value = match kind {
+                Kind::Bool => serde_json::Value::Bool(
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid value of field `{}`", path))?,
+                ),
+                _ => serde_json::Value::String(value),
+            };
+            if field.is_list() {
+                let entry = fields
+                    .entry(field.name().to_string())
+                    .or_insert_with(|| json!([]));
+                if let serde_json::Value::Array(values) = entry {
+                    values.push(value);
+                }
+            } else {
+                fields.insert(field.name().to_string(), value);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Reads the body and the trailers, fails if the body is larger than
+/// `limit`.
+async fn read_body(mut body: hyper::Body, limit: usize) -> Result<(Bytes, Option<HeaderMap>)> {
+    let mut data = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        anyhow::ensure!(data.len() + chunk.len() <= limit, "the body is too large");
+        data.extend_from_slice(&chunk);
+    }
+    Ok((data.freeze(), body.trailers().await?))
+}
+
+/// Maps the gRPC status code to the HTTP status code.
+fn http_status(code: u16) -> StatusCode {
+    match code {
+        0 => StatusCode::OK,
+        1 => StatusCode::from_u16(499).unwrap(),
+        3 | 9 | 11 => StatusCode::BAD_REQUEST,
+        4 => StatusCode::GATEWAY_TIMEOUT,
+        5 => StatusCode::NOT_FOUND,
+        6 | 10 => StatusCode::CONFLICT,
+        7 => StatusCode::FORBIDDEN,
+        8 => StatusCode::TOO_MANY_REQUESTS,
+        12 => StatusCode::NOT_IMPLEMENTED,
+        14 => StatusCode::SERVICE_UNAVAILABLE,
+        16 => StatusCode::UNAUTHORIZED,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn json_response(status: StatusCode, body: &serde_json::Value) -> Response {
+    Response::builder()
+        .status(status)
+        .content_type("application/json")
+        .body(body.to_string())
+}
+
+fn error_response(status: StatusCode, code: u16, message: &str) -> Response {
+    json_response(status, &json!({ "code": code, "message": message }))
+}
+
+async fn transcode_response(rule: &Rule, mut resp: Response, max_body_size: usize) -> Response {
+    let (data, trailers) = match read_body(resp.take_body().into(), max_body_size).await {
+        Ok(res) => res,
+        Err(err) => {
+            error!(error = %err, "failed to read the grpc response");
+            return error_response(StatusCode::BAD_GATEWAY, 14, "failed to read the response");
+        }
+    };
+
+    // the status is in the headers if the response is trailers-only
+    let status = trailers
+        .as_ref()
+        .filter(|trailers| trailers.contains_key(GRPC_STATUS))
+        .unwrap_or_else(|| resp.headers());
+    let code = match status
+        .get(GRPC_STATUS)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u16>().ok())
+    {
+        Some(code) => code,
+        None if resp.status().is_success() => 2,
+        None => return error_response(resp.status(), 2, "invalid grpc response"),
+    };
+    if code != 0 {
+        let message = status
+            .get(GRPC_MESSAGE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| percent_decode_str(value).decode_utf8_lossy().into_owned())
+            .unwrap_or_default();
+        return error_response(http_status(code), code, &message);
+    }
+
+    let mut messages = Vec::new();
+    let mut data = data;
+    while data.remaining() >= 5 {
+        let compressed = data.get_u8() != 0;
+        let len = data.get_u32() as usize;
+        if compressed || data.remaining() < len {
+            return error_response(StatusCode::BAD_GATEWAY, 13, "invalid grpc message");
+        }
+        let message = match DynamicMessage::decode(rule.method.output(), data.split_to(len)) {
+            Ok(message) => message,
+            Err(err) => return error_response(StatusCode::BAD_GATEWAY, 13, &err.to_string()),
+        };
+        let mut value = match serde_json::to_value(&message) {
+            Ok(value) => value,
+            Err(err) => return error_response(StatusCode::BAD_GATEWAY, 13, &err.to_string()),
+        };
+        if let Some(response_body) = &rule.response_body {
+            value = value
+                .get_mut(response_body.as_str())
+                .map(serde_json::Value::take)
+                .unwrap_or_default();
+        }
+        messages.push(value);
+    }
+
+    if rule.method.is_server_streaming() {
+        json_response(StatusCode::OK, &serde_json::Value::Array(messages))
+    } else {
+        match messages.into_iter().next() {
+            Some(value) => json_response(StatusCode::OK, &value),
+            None => error_response(StatusCode::BAD_GATEWAY, 13, "missing grpc message"),
+        }
+    }
+}
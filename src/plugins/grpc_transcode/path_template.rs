@@ -0,0 +1,155 @@
+use anyhow::Result;
+use percent_encoding::percent_decode_str;
+
+enum Matcher {
+    Literal(String),
+    /// `*`, matches a single segment.
+    Single,
+    /// `**`, matches zero or more segments.
+    Multi,
+}
+
+/// A path template of `google.api.http`, such as
+/// `/v1/{name=shelves/*/books/*}:publish`.
+pub struct PathTemplate {
+    /// The matchers of each segment, and the index of the variable it is
+    /// captured by.
+    segments: Vec<(Matcher, Option<usize>)>,
+    variables: Vec<String>,
+    verb: Option<String>,
+}
+
+fn parse_matcher(segment: &str) -> Matcher {
+    match segment {
+        "*" => Matcher::Single,
+        "**" => Matcher::Multi,
+        _ => Matcher::Literal(segment.to_string()),
+    }
+}
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> Result<Self> {
+        let path = template
+            .strip_prefix('/')
+            .ok_or_else(|| anyhow!("path template `{}` must start with `/`", template))?;
+
+        // the verb is after the last colon that is not inside a variable
+        let (path, verb) = match path.rfind(':') {
+            Some(idx) if !path[idx..].contains('}') && !path[idx..].contains('/') => {
+                (&path[..idx], Some(path[idx + 1..].to_string()))
+            }
+            _ => (path, None),
+        };
+
+        let mut segments = Vec::new();
+        let mut variables = Vec::new();
+        let mut rest = path;
+
+        while !rest.is_empty() {
+            if let Some(var) = rest.strip_prefix('{') {
+                let end = var
+                    .find('}')
+                    .ok_or_else(|| anyhow!("unclosed variable in path template `{}`", template))?;
+                let (field, pattern) = match var[..end].split_once('=') {
+                    Some((field, pattern)) => (field, pattern),
+                    None => (&var[..end], "*"),
+                };
+                for segment in pattern.split('/') {
+                    segments.push((parse_matcher(segment), Some(variables.len())));
+                }
+                variables.push(field.to_string());
+                rest = &var[end + 1..];
+            } else {
+                let end = rest.find('/').unwrap_or(rest.len());
+                segments.push((parse_matcher(&rest[..end]), None));
+                rest = &rest[end..];
+            }
+
+            match rest.strip_prefix('/') {
+                Some(tail) => rest = tail,
+                None if rest.is_empty() => {}
+                None => bail!("invalid path template `{}`", template),
+            }
+        }
+
+        Ok(Self {
+            segments,
+            variables,
+            verb,
+        })
+    }
+
+    /// Returns the field paths and the decoded values of the variables if
+    /// `path` matches the template.
+    pub fn matches(&self, path: &str) -> Option<Vec<(&str, String)>> {
+        let path = path.strip_prefix('/')?;
+        let path = match &self.verb {
+            Some(verb) => path.strip_suffix(verb.as_str())?.strip_suffix(':')?,
+            None => path,
+        };
+        let path_segments = if path.is_empty() {
+            Vec::new()
+        } else {
+            path.split('/').collect::<Vec<_>>()
+        };
+
+        let mut captures = vec![Vec::new(); self.variables.len()];
+        if !match_segments(&self.segments, &path_segments, &mut captures) {
+            return None;
+        }
+
+        Some(
+            self.variables
+                .iter()
+                .zip(captures)
+                .map(|(field, segments)| {
+                    let value = segments
+                        .iter()
+                        .map(|segment| percent_decode_str(segment).decode_utf8_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    (field.as_str(), value)
+                })
+                .collect(),
+        )
+    }
+}
+
+fn match_segments<'a>(
+    matchers: &[(Matcher, Option<usize>)],
+    path: &[&'a str],
+    captures: &mut Vec<Vec<&'a str>>,
+) -> bool {
+    let ((matcher, var), matchers) = match matchers.split_first() {
+        Some(res) => res,
+        None => return path.is_empty(),
+    };
+
+    let max_len = match matcher {
+        Matcher::Literal(literal) => match path.first() {
+            Some(segment) if segment == literal => 1,
+            _ => return false,
+        },
+        Matcher::Single if path.is_empty() => return false,
+        Matcher::Single => 1,
+        Matcher::Multi => path.len(),
+    };
+    let min_len = match matcher {
+        Matcher::Multi => 0,
+        _ => 1,
+    };
+
+    for len in (min_len..=max_len).rev() {
+        let captured = var.map(|var| captures[var].len()).unwrap_or_default();
+        if let Some(var) = *var {
+            captures[var].extend_from_slice(&path[..len]);
+        }
+        if match_segments(matchers, &path[len..], captures) {
+            return true;
+        }
+        if let Some(var) = *var {
+            captures[var].truncate(captured);
+        }
+    }
+    false
+}
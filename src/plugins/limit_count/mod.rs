@@ -122,6 +122,10 @@ impl LimitCount {
 
 #[async_trait::async_trait]
 impl Plugin for LimitCount {
+    fn name(&self) -> &'static str {
+        "limitCount"
+    }
+
     fn priority(&self) -> i32 {
         1000
     }
@@ -0,0 +1,137 @@
+use std::{io::Cursor, sync::Arc};
+
+use anyhow::Result;
+use poem::{http::StatusCode, Body, Endpoint, Request, Response};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncReadExt, sync::Semaphore};
+
+use crate::{
+    config::{PluginConfig, ServiceTargetConfig},
+    plugins::{NextPlugin, Plugin, PluginContext},
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Config {
+    target: Box<dyn ServiceTargetConfig>,
+    #[serde(default = "default_percentage")]
+    percentage: f64,
+    #[serde(default = "default_max_body_size")]
+    max_body_size: usize,
+    /// The maximum number of in-flight mirror requests, the requests beyond
+    /// it are not mirrored.
+    #[serde(default = "default_max_concurrency")]
+    max_concurrency: usize,
+}
+
+const fn default_percentage() -> f64 {
+    100.0
+}
+
+const fn default_max_body_size() -> usize {
+    64 * 1024
+}
+
+const fn default_max_concurrency() -> usize {
+    100
+}
+
+#[typetag::serde(name = "mirror")]
+#[async_trait::async_trait]
+impl PluginConfig for Config {
+    async fn create(&self) -> Result<Arc<dyn Plugin>> {
+        anyhow::ensure!(
+            (0.0..=100.0).contains(&self.percentage),
+            "invalid mirror percentage `{}`",
+            self.percentage
+        );
+
+        Ok(Arc::new(Mirror {
+            target: self.target.create()?,
+            percentage: self.percentage,
+            max_body_size: self.max_body_size,
+            semaphore: Arc::new(Semaphore::new(self.max_concurrency)),
+        }))
+    }
+}
+
+struct Mirror {
+    target: Arc<dyn Endpoint<Output = Response>>,
+    percentage: f64,
+    max_body_size: usize,
+    semaphore: Arc<Semaphore>,
+}
+
+impl Mirror {
+    fn sampled(&self) -> bool {
+        self.percentage >= 100.0 || rand::random::<f64>() * 100.0 < self.percentage
+    }
+}
+
+#[async_trait::async_trait]
+impl Plugin for Mirror {
+    fn name(&self) -> &'static str {
+        "mirror"
+    }
+
+    fn priority(&self) -> i32 {
+        10
+    }
+
+    async fn call(
+        &self,
+        mut req: Request,
+        ctx: &mut PluginContext,
+        next: NextPlugin<'_>,
+    ) -> Response {
+        if !self.sampled() {
+            return next.call(ctx, req).await;
+        }
+        let permit = match self.semaphore.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                debug!("too many in-flight mirror requests");
+                return next.call(ctx, req).await;
+            }
+        };
+
+        // read at most `max_body_size + 1` bytes, so we know whether the body fits
+        // without holding back the rest of the stream from the primary request.
+        let mut reader = req.take_body().into_async_read();
+        let mut data = Vec::new();
+        if let Err(err) = (&mut reader)
+            .take(self.max_body_size as u64 + 1)
+            .read_to_end(&mut data)
+            .await
+        {
+            error!(error = %err, "failed to read the request body for mirroring");
+            return StatusCode::BAD_REQUEST.into();
+        }
+
+        if data.len() <= self.max_body_size {
+            let mut builder = Request::builder()
+                .method(req.method().clone())
+                .uri(req.uri().clone())
+                .version(req.version());
+            for (name, value) in req.headers() {
+                builder = builder.header(name.clone(), value.clone());
+            }
+            let mirror_req = builder.body(data.clone());
+
+            let target = self.target.clone();
+            tokio::spawn(async move {
+                let _permit = permit;
+                let resp = target.call(mirror_req).await;
+                debug!(status = %resp.status(), "mirror request completed");
+            });
+        } else {
+            debug!(
+                max_body_size = self.max_body_size,
+                "request body is too large to mirror"
+            );
+        }
+
+        req.set_body(Body::from_async_read(Cursor::new(data).chain(reader)));
+        next.call(ctx, req).await
+    }
+}
@@ -1,17 +1,97 @@
 mod auth_basic;
 mod circuit_breaker;
+mod grpc_transcode;
 mod limit_count;
+mod mirror;
 mod response_rewrite;
 
 use std::{
     ops::{Deref, DerefMut},
     sync::Arc,
+    time::Duration,
 };
 
-use poem::{web::RemoteAddr, Endpoint, Request, Response};
+use hyper::body::HttpBody;
+use poem::{web::RemoteAddr, Body, Endpoint, Request, Response};
 use tera::Tera;
 
-#[derive(Default)]
+/// The name of the consumer that was matched for the request.
+///
+/// It is inserted into the request extensions before the plugin chain is
+/// called, so service targets can access it as well.
+#[derive(Debug, Clone)]
+pub struct ConsumerName(pub String);
+
+/// Marks a response whose body is a long-lived stream, such as Server-Sent
+/// Events or a chunked response of unknown length.
+///
+/// The service targets insert it into the response extensions, the plugins
+/// must forward the bodies of such responses chunk by chunk instead of
+/// buffering them.
+#[derive(Debug, Clone, Copy)]
+pub struct StreamingBody;
+
+/// Returns `true` if the body of `resp` is a stream.
+pub fn is_streaming_response(resp: &Response) -> bool {
+    resp.extensions().get::<StreamingBody>().is_some() || is_event_stream(resp)
+}
+
+/// Returns `true` if `resp` is Server-Sent Events.
+pub fn is_event_stream(resp: &Response) -> bool {
+    resp.content_type()
+        .map(|content_type| content_type.starts_with("text/event-stream"))
+        .unwrap_or_default()
+}
+
+/// Ends `body` if no chunk is received for `idle_timeout`, the trailers are
+/// kept if the body completes.
+///
+/// The idle body is aborted so that the client does not take it as
+/// complete, unless `close_cleanly` is set for the streams that the clients
+/// can resume, such as Server-Sent Events.
+pub fn with_idle_timeout(body: Body, idle_timeout: Duration, close_cleanly: bool) -> Body {
+    let mut body: hyper::Body = body.into();
+    let (mut sender, new_body) = hyper::Body::channel();
+
+    tokio::spawn(async move {
+        loop {
+            match tokio::time::timeout(idle_timeout, body.data()).await {
+                Ok(Some(Ok(data))) => {
+                    if sender.send_data(data).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(Some(Err(err))) => {
+                    debug!(error = %err, "failed to read the stream.");
+                    sender.abort();
+                    return;
+                }
+                Ok(None) => break,
+                Err(_) if close_cleanly => {
+                    debug!("the stream is idle, close it.");
+                    return;
+                }
+                Err(_) => {
+                    debug!("the stream is idle, abort it.");
+                    sender.abort();
+                    return;
+                }
+            }
+        }
+        if let Ok(Some(trailers)) = body.trailers().await {
+            let _ = sender.send_trailers(trailers).await;
+        }
+    });
+
+    new_body.into()
+}
+
+/// The context shared by the plugins of a request.
+///
+/// A copy of it is inserted into the request extensions before the service
+/// target is called if the target uses it, so the targets can render
+/// templates with it.
+#[derive(Default, Clone)]
 pub struct PluginContext {
     tera_ctx: tera::Context,
 }
@@ -49,6 +129,7 @@ impl PluginContext {
 pub struct NextPlugin<'a> {
     chain: &'a [Arc<dyn Plugin>],
     endpoint: &'a dyn Endpoint<Output = Response>,
+    insert_context: bool,
 }
 
 impl<'a> NextPlugin<'a> {
@@ -56,11 +137,16 @@ impl<'a> NextPlugin<'a> {
     pub fn new(
         chain: &'a [Arc<dyn Plugin>],
         endpoint: &'a dyn Endpoint<Output = Response>,
+        insert_context: bool,
     ) -> Self {
-        Self { chain, endpoint }
+        Self {
+            chain,
+            endpoint,
+            insert_context,
+        }
     }
 
-    pub async fn call(self, ctx: &mut PluginContext, req: Request) -> Response {
+    pub async fn call(self, ctx: &mut PluginContext, mut req: Request) -> Response {
         if let Some((first, next)) = self.chain.split_first() {
             first
                 .call(
@@ -69,10 +155,14 @@ impl<'a> NextPlugin<'a> {
                     NextPlugin {
                         chain: next,
                         endpoint: self.endpoint,
+                        insert_context: self.insert_context,
                     },
                 )
                 .await
         } else {
+            if self.insert_context {
+                req.extensions_mut().insert(ctx.clone());
+            }
             self.endpoint.call(req).await
         }
     }
@@ -80,12 +170,34 @@ impl<'a> NextPlugin<'a> {
 
 #[async_trait::async_trait]
 pub trait Plugin: Sync + Send + 'static {
+    /// The type of the plugin, the plugins of the same type override each
+    /// other when they are inherited.
+    fn name(&self) -> &'static str;
+
     fn priority(&self) -> i32;
 
     async fn call(&self, req: Request, ctx: &mut PluginContext, next: NextPlugin<'_>) -> Response;
 }
 
+/// Authenticates the consumers.
+///
+/// The consumer of a request is found by the identifier of the credentials,
+/// such as the username, and then only the plugin of that consumer verifies
+/// the request.
 #[async_trait::async_trait]
 pub trait AuthPlugin: Sync + Send + 'static {
-    async fn auth(&self, req: &Request) -> bool;
+    /// The kind of the credentials, the plugins of the same kind extract the
+    /// identifiers from the requests in the same way.
+    fn kind(&self) -> &'static str;
+
+    /// Returns the identifier of the credentials of the consumer.
+    fn credential_id(&self) -> &str;
+
+    /// Extracts the identifier of the credentials from the request, returns
+    /// `None` if the request does not carry such credentials.
+    fn extract(&self, req: &Request) -> Option<String>;
+
+    /// Verifies the credentials of the request, whose identifier matches
+    /// this plugin.
+    async fn verify(&self, req: &Request) -> bool;
 }
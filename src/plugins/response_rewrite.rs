@@ -77,6 +77,10 @@ struct ResponseRewrite {
 
 #[async_trait::async_trait]
 impl Plugin for ResponseRewrite {
+    fn name(&self) -> &'static str {
+        "responseRewrite"
+    }
+
     fn priority(&self) -> i32 {
         0
     }
@@ -0,0 +1,290 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures_util::future;
+use poem::{
+    http::{header, Method, StatusCode},
+    Body, Endpoint, Request, Response,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tera::Tera;
+use tokio::io::AsyncReadExt;
+
+use crate::{config::ServiceTargetConfig, plugins::PluginContext};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CallConfig {
+    /// The key of the response body in the aggregated response.
+    name: String,
+    target: Box<dyn ServiceTargetConfig>,
+    /// Overrides the method of the request.
+    #[serde(default)]
+    method: Option<String>,
+    /// The template of the path and query of the request, with the variables
+    /// of the plugin context, `path` and `query` of the request.
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    /// The whole request fails if a required call fails, otherwise the value
+    /// of the call is `null`.
+    #[serde(default = "default_required")]
+    required: bool,
+}
+
+const fn default_required() -> bool {
+    true
+}
+
+/// Calls the targets in parallel and merges their JSON responses into an
+/// object.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AggregateConfig {
+    calls: Vec<CallConfig>,
+    /// The maximum size of the request body and of the response body of
+    /// each call.
+    #[serde(default = "default_max_body_size")]
+    max_body_size: usize,
+}
+
+const fn default_max_body_size() -> usize {
+    4 * 1024 * 1024
+}
+
+#[typetag::serde(name = "aggregate")]
+impl ServiceTargetConfig for AggregateConfig {
+    fn create(&self) -> Result<Arc<dyn Endpoint<Output = Response>>> {
+        anyhow::ensure!(!self.calls.is_empty(), "at least one call is required");
+
+        let mut calls: Vec<Call> = Vec::new();
+        let mut tera = Tera::default();
+
+        for config in &self.calls {
+            anyhow::ensure!(
+                calls.iter().all(|call| call.name != config.name),
+                "call `{}` is defined more than once",
+                config.name
+            );
+            let method = match &config.method {
+                Some(method) => Some(
+                    Method::from_bytes(method.to_uppercase().as_bytes())
+                        .with_context(|| format!("invalid method `{}`", method))?,
+                ),
+                None => None,
+            };
+            if let Some(path) = &config.path {
+                tera.add_raw_template(&config.name, path).with_context(|| {
+                    format!("failed to parse the path of call `{}`", config.name)
+                })?;
+            }
+
+            calls.push(Call {
+                name: config.name.clone(),
+                target: config
+                    .target
+                    .create()
+                    .with_context(|| format!("invalid target of call `{}`", config.name))?,
+                method,
+                has_path: config.path.is_some(),
+                timeout: config.timeout_ms.map(Duration::from_millis),
+                required: config.required,
+            });
+        }
+
+        Ok(Arc::new(Aggregate {
+            calls,
+            tera,
+            max_body_size: self.max_body_size,
+        }))
+    }
+
+    fn uses_plugin_context(&self) -> bool {
+        true
+    }
+}
+
+struct Call {
+    name: String,
+    target: Arc<dyn Endpoint<Output = Response>>,
+    method: Option<Method>,
+    has_path: bool,
+    timeout: Option<Duration>,
+    required: bool,
+}
+
+enum CallError {
+    Timeout,
+    Other(anyhow::Error),
+}
+
+/// Reads the body, `None` if it is larger than `limit`.
+async fn read_body(body: Body, limit: usize) -> std::io::Result<Option<Vec<u8>>> {
+    let mut data = Vec::new();
+    body.into_async_read()
+        .take(limit as u64 + 1)
+        .read_to_end(&mut data)
+        .await?;
+    Ok(Some(data).filter(|data| data.len() <= limit))
+}
+
+impl Call {
+    async fn call(&self, req: Request, max_body_size: usize) -> Result<Value, CallError> {
+        let fut = async {
+            let resp = self.target.call(req).await;
+            anyhow::ensure!(
+                resp.status().is_success(),
+                "responded with status {}",
+                resp.status().as_u16()
+            );
+            let body = read_body(resp.into_body(), max_body_size)
+                .await
+                .context("failed to read the response body")?
+                .ok_or_else(|| anyhow!("the response body is too large"))?;
+            if body.is_empty() {
+                return Ok(Value::Null);
+            }
+            serde_json::from_slice(&body).context("the response body is not JSON")
+        };
+
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, fut)
+                .await
+                .map_err(|_| CallError::Timeout)?
+                .map_err(CallError::Other),
+            None => fut.await.map_err(CallError::Other),
+        }
+    }
+
+    /// Logs the error and returns the status of the failed request.
+    fn report(&self, err: CallError) -> StatusCode {
+        let (status, err) = match err {
+            CallError::Timeout => (StatusCode::GATEWAY_TIMEOUT, anyhow!("timed out")),
+            CallError::Other(err) => (StatusCode::BAD_GATEWAY, err),
+        };
+        warn!(
+            call = %self.name,
+            required = self.required,
+            error = %format!("{:#}", err),
+            "aggregate call failed.",
+        );
+        status
+    }
+}
+
+struct Aggregate {
+    calls: Vec<Call>,
+    tera: Tera,
+    max_body_size: usize,
+}
+
+impl Aggregate {
+    fn make_request(
+        &self,
+        call: &Call,
+        req: &Request,
+        ctx: &PluginContext,
+        body: &Bytes,
+    ) -> Request {
+        let uri = if call.has_path {
+            let path = ctx.render_template(&self.tera, &call.name);
+            path.parse().unwrap_or_else(|_| {
+                warn!(call = %call.name, path = %path, "invalid path of the aggregate call.");
+                req.uri().clone()
+            })
+        } else {
+            req.uri().clone()
+        };
+
+        let method = call.method.as_ref().unwrap_or_else(|| req.method());
+        let mut builder = Request::builder()
+            .method(method.clone())
+            .uri(uri)
+            .version(req.version());
+        for (name, value) in req.headers() {
+            // the responses are parsed as JSON, so they must not be compressed
+            if name == header::ACCEPT_ENCODING
+                || (method != req.method()
+                    && (name == header::CONTENT_LENGTH || name == header::TRANSFER_ENCODING))
+            {
+                continue;
+            }
+            builder = builder.header(name.clone(), value.clone());
+        }
+        let mut call_req = builder.body(body.clone());
+        call_req.extensions_mut().insert(ctx.clone());
+        call_req
+    }
+}
+
+#[async_trait::async_trait]
+impl Endpoint for Aggregate {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> Self::Output {
+        let mut ctx = req
+            .extensions()
+            .get::<PluginContext>()
+            .cloned()
+            .unwrap_or_else(|| PluginContext::new(&req));
+        let body = match read_body(req.take_body(), self.max_body_size).await {
+            Ok(Some(body)) => Bytes::from(body),
+            Ok(None) => return StatusCode::PAYLOAD_TOO_LARGE.into(),
+            Err(err) => {
+                warn!(error = %err, "failed to read the request body.");
+                return StatusCode::BAD_REQUEST.into();
+            }
+        };
+
+        ctx.insert("path", req.uri().path());
+        ctx.insert("query", req.uri().query().unwrap_or_default());
+
+        // the optional calls are completed even if they fail, while the first
+        // failed required call fails the whole request immediately
+        let mut required = Vec::new();
+        let mut optional = Vec::new();
+        for call in &self.calls {
+            let call_req = self.make_request(call, &req, &ctx, &body);
+            let fut = call.call(call_req, self.max_body_size);
+            if call.required {
+                required.push(async move {
+                    match fut.await {
+                        Ok(value) => Ok((call, value)),
+                        Err(err) => Err(call.report(err)),
+                    }
+                });
+            } else {
+                optional.push(async move {
+                    match fut.await {
+                        Ok(value) => (call, value),
+                        Err(err) => {
+                            call.report(err);
+                            (call, Value::Null)
+                        }
+                    }
+                });
+            }
+        }
+
+        let (required, optional) = match future::try_join(future::try_join_all(required), async {
+            Ok(future::join_all(optional).await)
+        })
+        .await
+        {
+            Ok(results) => results,
+            Err(status) => return status.into(),
+        };
+
+        let mut values = Map::new();
+        for (call, value) in required.into_iter().chain(optional) {
+            values.insert(call.name.clone(), value);
+        }
+
+        Response::builder()
+            .content_type("application/json")
+            .body(Value::Object(values).to_string())
+    }
+}
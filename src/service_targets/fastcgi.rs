@@ -0,0 +1,443 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use bytes::{BufMut, Bytes, BytesMut};
+use poem::{
+    http::{header, HeaderValue, StatusCode},
+    web::RemoteAddr,
+    Body, Endpoint, Request, Response,
+};
+use serde::{Deserialize, Serialize};
+use tera::Tera;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UnixStream},
+};
+
+use crate::{
+    config::ServiceTargetConfig,
+    plugins::{PluginContext, StreamingBody},
+};
+
+const FCGI_VERSION: u8 = 1;
+const FCGI_BEGIN_REQUEST: u8 = 1;
+const FCGI_END_REQUEST: u8 = 3;
+const FCGI_PARAMS: u8 = 4;
+const FCGI_STDIN: u8 = 5;
+const FCGI_STDOUT: u8 = 6;
+const FCGI_STDERR: u8 = 7;
+const FCGI_RESPONDER: u16 = 1;
+const REQUEST_ID: u16 = 1;
+const MAX_CONTENT_LENGTH: usize = 65535;
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FastCgiConfig {
+    /// The address of the FastCGI server, `host:port` or `unix:/path`.
+    address: String,
+    /// The template of `SCRIPT_FILENAME`, with the variables of the plugin
+    /// context and `path` of the request.
+    script_filename: String,
+    #[serde(default)]
+    document_root: Option<String>,
+    /// The additional parameters, the values are templates like
+    /// `scriptFilename`.
+    #[serde(default)]
+    params: HashMap<String, String>,
+    #[serde(default)]
+    connect_timeout_ms: Option<u64>,
+    /// Timeout of waiting for the response headers.
+    #[serde(default)]
+    read_timeout_ms: Option<u64>,
+    /// The maximum size of the request body, which is buffered to send
+    /// `CONTENT_LENGTH`.
+    #[serde(default = "default_max_body_size")]
+    max_body_size: usize,
+}
+
+const fn default_max_body_size() -> usize {
+    8 * 1024 * 1024
+}
+
+#[typetag::serde(name = "fastcgi")]
+impl ServiceTargetConfig for FastCgiConfig {
+    fn create(&self) -> Result<Arc<dyn Endpoint<Output = Response>>> {
+        let address = match self.address.strip_prefix("unix:") {
+            Some(path) => Address::Unix(path.to_string()),
+            None => Address::Tcp(self.address.clone()),
+        };
+
+        let mut tera = Tera::default();
+        tera.add_raw_template("SCRIPT_FILENAME", &self.script_filename)
+            .context("failed to parse the template of `scriptFilename`")?;
+        let mut params = Vec::new();
+        for (name, template) in &self.params {
+            tera.add_raw_template(name, template)
+                .with_context(|| format!("failed to parse the template of param `{}`", name))?;
+            params.push(name.clone());
+        }
+
+        Ok(Arc::new(FastCgi {
+            address,
+            tera,
+            document_root: self.document_root.clone(),
+            params,
+            connect_timeout: self.connect_timeout_ms.map(Duration::from_millis),
+            read_timeout: self.read_timeout_ms.map(Duration::from_millis),
+            max_body_size: self.max_body_size,
+        }))
+    }
+
+    fn uses_plugin_context(&self) -> bool {
+        true
+    }
+}
+
+enum Address {
+    Tcp(String),
+    Unix(String),
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Address::Tcp(addr) => f.write_str(addr),
+            Address::Unix(path) => write!(f, "unix:{}", path),
+        }
+    }
+}
+
+struct FastCgi {
+    address: Address,
+    tera: Tera,
+    document_root: Option<String>,
+    params: Vec<String>,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    max_body_size: usize,
+}
+
+fn write_record(buf: &mut BytesMut, ty: u8, content: &[u8]) {
+    let padding = (8 - content.len() % 8) % 8;
+    buf.put_u8(FCGI_VERSION);
+    buf.put_u8(ty);
+    buf.put_u16(REQUEST_ID);
+    buf.put_u16(content.len() as u16);
+    buf.put_u8(padding as u8);
+    buf.put_u8(0);
+    buf.put_slice(content);
+    buf.put_bytes(0, padding);
+}
+
+/// Writes the records of the stream `ty`, including the empty record that
+/// ends the stream.
+fn write_stream(buf: &mut BytesMut, ty: u8, content: &[u8]) {
+    for chunk in content.chunks(MAX_CONTENT_LENGTH) {
+        write_record(buf, ty, chunk);
+    }
+    write_record(buf, ty, &[]);
+}
+
+fn write_length(buf: &mut BytesMut, len: usize) {
+    if len < 128 {
+        buf.put_u8(len as u8);
+    } else {
+        buf.put_u32(len as u32 | 0x8000_0000);
+    }
+}
+
+fn encode_params(params: &[(String, String)]) -> BytesMut {
+    let mut buf = BytesMut::new();
+    for (name, value) in params {
+        write_length(&mut buf, name.len());
+        write_length(&mut buf, value.len());
+        buf.put_slice(name.as_bytes());
+        buf.put_slice(value.as_bytes());
+    }
+    buf
+}
+
+/// Reads a record, returns the type and the content.
+async fn read_record(stream: &mut (impl AsyncRead + Unpin)) -> std::io::Result<(u8, Bytes)> {
+    let mut header = [0; 8];
+    stream.read_exact(&mut header).await?;
+    let content_length = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let padding_length = header[6] as usize;
+    let mut content = vec![0; content_length + padding_length];
+    stream.read_exact(&mut content).await?;
+    content.truncate(content_length);
+    Ok((header[1], content.into()))
+}
+
+/// Finds the end of the CGI headers, returns the end of the headers and the
+/// start of the body.
+fn find_headers_end(data: &[u8]) -> Option<(usize, usize)> {
+    for (idx, window) in data.windows(2).enumerate() {
+        if window == b"\n\n" {
+            return Some((idx, idx + 2));
+        }
+        if window == b"\r\n" && data[idx + 2..].starts_with(b"\r\n") {
+            return Some((idx, idx + 4));
+        }
+    }
+    None
+}
+
+impl FastCgi {
+    fn params(&self, req: &Request, content_length: usize) -> Vec<(String, String)> {
+        let mut ctx = req
+            .extensions()
+            .get::<PluginContext>()
+            .cloned()
+            .unwrap_or_else(|| PluginContext::new(req));
+        ctx.insert("path", req.uri().path());
+
+        let uri = req.original_uri();
+        let host = req
+            .headers()
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .unwrap_or_default();
+        let (server_name, server_port) = match host.rsplit_once(':') {
+            Some((name, port)) if !port.contains(']') => (name, port),
+            _ => (host, "80"),
+        };
+
+        let mut params = vec![
+            ("GATEWAY_INTERFACE".to_string(), "CGI/1.1".to_string()),
+            ("SERVER_SOFTWARE".to_string(), "poem-gateway".to_string()),
+            (
+                "SERVER_PROTOCOL".to_string(),
+                format!("{:?}", req.version()),
+            ),
+            ("REQUEST_METHOD".to_string(), req.method().to_string()),
+            (
+                "SCRIPT_FILENAME".to_string(),
+                ctx.render_template(&self.tera, "SCRIPT_FILENAME"),
+            ),
+            ("SCRIPT_NAME".to_string(), req.uri().path().to_string()),
+            ("DOCUMENT_URI".to_string(), req.uri().path().to_string()),
+            (
+                "REQUEST_URI".to_string(),
+                uri.path_and_query()
+                    .map(|path_and_query| path_and_query.to_string())
+                    .unwrap_or_else(|| uri.path().to_string()),
+            ),
+            (
+                "QUERY_STRING".to_string(),
+                uri.query().unwrap_or_default().to_string(),
+            ),
+            ("CONTENT_LENGTH".to_string(), content_length.to_string()),
+            (
+                "CONTENT_TYPE".to_string(),
+                req.content_type().unwrap_or_default().to_string(),
+            ),
+            ("SERVER_NAME".to_string(), server_name.to_string()),
+            ("SERVER_PORT".to_string(), server_port.to_string()),
+        ];
+        if let Some(document_root) = &self.document_root {
+            params.push(("DOCUMENT_ROOT".to_string(), document_root.clone()));
+        }
+        if let RemoteAddr::SocketAddr(addr) = req.remote_addr() {
+            params.push(("REMOTE_ADDR".to_string(), addr.ip().to_string()));
+            params.push(("REMOTE_PORT".to_string(), addr.port().to_string()));
+        }
+
+        for (name, value) in req.headers() {
+            // `HTTP_PROXY` is not passed, see https://httpoxy.org
+            if name == header::CONTENT_TYPE
+                || name == header::CONTENT_LENGTH
+                || name.as_str() == "proxy"
+            {
+                continue;
+            }
+            if let Ok(value) = value.to_str() {
+                params.push((
+                    format!("HTTP_{}", name.as_str().to_uppercase().replace('-', "_")),
+                    value.to_string(),
+                ));
+            }
+        }
+
+        for name in &self.params {
+            let value = ctx.render_template(&self.tera, name);
+            match params.iter_mut().find(|(param_name, _)| param_name == name) {
+                Some((_, param_value)) => *param_value = value,
+                None => params.push((name.clone(), value)),
+            }
+        }
+
+        params
+    }
+
+    async fn send<S>(&self, mut stream: S, req: &Request, body: &[u8]) -> Result<Response>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let mut buf = BytesMut::new();
+        let mut begin_request = [0; 8];
+        begin_request[..2].copy_from_slice(&FCGI_RESPONDER.to_be_bytes());
+        write_record(&mut buf, FCGI_BEGIN_REQUEST, &begin_request);
+        write_stream(
+            &mut buf,
+            FCGI_PARAMS,
+            &encode_params(&self.params(req, body.len())),
+        );
+        write_stream(&mut buf, FCGI_STDIN, body);
+        stream.write_all(&buf).await?;
+        stream.flush().await?;
+
+        let read_headers = async {
+            let mut stdout = BytesMut::new();
+            loop {
+                let (ty, content) = read_record(&mut stream).await?;
+                match ty {
+                    FCGI_STDOUT => {
+                        stdout.extend_from_slice(&content);
+                        if find_headers_end(&stdout).is_some() {
+                            return Ok::<_, anyhow::Error>((stdout, false));
+                        }
+                    }
+                    FCGI_STDERR => {
+                        warn!(message = %String::from_utf8_lossy(&content), "fastcgi stderr")
+                    }
+                    FCGI_END_REQUEST => return Ok((stdout, true)),
+                    _ => {}
+                }
+            }
+        };
+        let (mut stdout, ended) = match self.read_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, read_headers).await??,
+            None => read_headers.await?,
+        };
+
+        let (headers_end, body_start) =
+            find_headers_end(&stdout).ok_or_else(|| anyhow!("invalid fastcgi response headers"))?;
+        let headers = stdout.split_to(body_start);
+        let mut resp = Response::default();
+        let mut has_status = false;
+        for line in String::from_utf8_lossy(&headers[..headers_end]).lines() {
+            let (name, value) = match line.split_once(':') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => continue,
+            };
+            if name.eq_ignore_ascii_case("status") {
+                let code = value.split_whitespace().next().unwrap_or_default();
+                has_status = true;
+                resp.set_status(
+                    code.parse::<u16>()
+                        .ok()
+                        .and_then(|code| StatusCode::from_u16(code).ok())
+                        .ok_or_else(|| anyhow!("invalid fastcgi status `{}`", value))?,
+                );
+            } else if let (Ok(name), Ok(value)) = (
+                name.parse::<header::HeaderName>(),
+                HeaderValue::from_str(value),
+            ) {
+                resp.headers_mut().append(name, value);
+            }
+        }
+        // a `Location` header without `Status` is a redirect, see RFC 3875
+        if resp.headers().contains_key(header::LOCATION) && !has_status {
+            resp.set_status(StatusCode::FOUND);
+        }
+
+        if ended {
+            resp.set_body(stdout.freeze());
+            return Ok(resp);
+        }
+
+        let (mut sender, body) = hyper::Body::channel();
+        tokio::spawn(async move {
+            if !stdout.is_empty() && sender.send_data(stdout.freeze()).await.is_err() {
+                return;
+            }
+            loop {
+                match read_record(&mut stream).await {
+                    Ok((FCGI_STDOUT, content)) if !content.is_empty() => {
+                        if sender.send_data(content).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok((FCGI_STDERR, content)) => {
+                        warn!(message = %String::from_utf8_lossy(&content), "fastcgi stderr")
+                    }
+                    Ok((FCGI_END_REQUEST, _)) => return,
+                    Ok(_) => {}
+                    Err(err) => {
+                        warn!(error = %err, "failed to read the fastcgi response");
+                        sender.abort();
+                        return;
+                    }
+                }
+            }
+        });
+        if !resp.headers().contains_key(header::CONTENT_LENGTH) {
+            resp.extensions_mut().insert(StreamingBody);
+        }
+        resp.set_body(Body::from(body));
+        Ok(resp)
+    }
+
+    /// Reads the request body, `None` if it is larger than `max_body_size`.
+    async fn read_body(&self, req: &mut Request) -> std::io::Result<Option<Vec<u8>>> {
+        let mut body = Vec::new();
+        req.take_body()
+            .into_async_read()
+            .take(self.max_body_size as u64 + 1)
+            .read_to_end(&mut body)
+            .await?;
+        Ok(Some(body).filter(|body| body.len() <= self.max_body_size))
+    }
+
+    async fn call_fastcgi(&self, req: &Request, body: &[u8]) -> Result<Response> {
+        let connect = async {
+            Ok::<_, anyhow::Error>(match &self.address {
+                Address::Tcp(addr) => Stream::Tcp(TcpStream::connect(addr).await?),
+                Address::Unix(path) => Stream::Unix(UnixStream::connect(path).await?),
+            })
+        };
+        let stream = match self.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connect).await??,
+            None => connect.await?,
+        };
+
+        match stream {
+            Stream::Tcp(stream) => self.send(stream, req, body).await,
+            Stream::Unix(stream) => self.send(stream, req, body).await,
+        }
+    }
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+#[async_trait::async_trait]
+impl Endpoint for FastCgi {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> Self::Output {
+        let body = match self.read_body(&mut req).await {
+            Ok(Some(body)) => body,
+            Ok(None) => return StatusCode::PAYLOAD_TOO_LARGE.into(),
+            Err(err) => {
+                warn!(error = %err, "failed to read the request body.");
+                return StatusCode::BAD_REQUEST.into();
+            }
+        };
+
+        match self.call_fastcgi(&req, &body).await {
+            Ok(resp) => resp,
+            Err(err) => {
+                error!(address = %self.address, error = %err, "fastcgi error");
+                if err.is::<tokio::time::error::Elapsed>() {
+                    StatusCode::GATEWAY_TIMEOUT.into()
+                } else {
+                    StatusCode::BAD_GATEWAY.into()
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use poem::{
+    http::{header::HeaderName, HeaderValue, Method, StatusCode},
+    Endpoint, Request, Response,
+};
+use serde::{Deserialize, Serialize};
+use tera::Tera;
+use tokio::io::AsyncReadExt;
+
+use crate::{config::ServiceTargetConfig, plugins::PluginContext};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MatchConfig {
+    #[serde(default)]
+    methods: Vec<String>,
+    /// The path pattern, `:name` matches a segment and `*name` matches the
+    /// rest of the path, they are available as `params.name` in templates.
+    #[serde(default)]
+    path: Option<String>,
+    /// The headers that must be present with the exact values.
+    #[serde(default)]
+    headers: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MockResponseConfig {
+    #[serde(default, rename = "match")]
+    matches: Option<MatchConfig>,
+    #[serde(default = "default_status_code")]
+    status_code: u16,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    body_base64: bool,
+    #[serde(default)]
+    latency_ms: u64,
+}
+
+const fn default_status_code() -> u16 {
+    200
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MockConfig {
+    /// The responses, the first one that matches the request is returned.
+    responses: Vec<MockResponseConfig>,
+    /// The maximum size of the request body available to the templates.
+    #[serde(default = "default_max_body_size")]
+    max_body_size: usize,
+}
+
+const fn default_max_body_size() -> usize {
+    1024 * 1024
+}
+
+#[typetag::serde(name = "mock")]
+impl ServiceTargetConfig for MockConfig {
+    fn create(&self) -> Result<Arc<dyn Endpoint<Output = Response>>> {
+        let mut responses = Vec::new();
+        for (idx, response) in self.responses.iter().enumerate() {
+            responses.push(
+                response
+                    .create()
+                    .with_context(|| format!("invalid mock response #{}", idx))?,
+            );
+        }
+        Ok(Arc::new(Mock {
+            responses,
+            max_body_size: self.max_body_size,
+        }))
+    }
+
+    fn uses_plugin_context(&self) -> bool {
+        true
+    }
+}
+
+impl MockResponseConfig {
+    fn create(&self) -> Result<MockResponse> {
+        let mut methods = Vec::new();
+        let mut path = None;
+        let mut match_headers = Vec::new();
+
+        if let Some(matches) = &self.matches {
+            for method in &matches.methods {
+                methods.push(
+                    Method::from_bytes(method.to_uppercase().as_bytes())
+                        .with_context(|| format!("invalid method `{}`", method))?,
+                );
+            }
+            path = matches.path.as_deref().map(PathPattern::parse);
+            for (name, value) in &matches.headers {
+                match_headers.push((
+                    name.parse::<HeaderName>()
+                        .with_context(|| format!("failed to parse header name `{}`", name))?,
+                    value.clone(),
+                ));
+            }
+        }
+
+        let mut tera = Tera::default();
+        let mut headers = Vec::new();
+        for (name, template) in &self.headers {
+            let header_name: HeaderName = name
+                .parse()
+                .with_context(|| format!("failed to parse header name `{}`", name))?;
+            let template_name = format!("header-{}", header_name);
+            tera.add_raw_template(&template_name, template)
+                .with_context(|| format!("failed to parse the value of header `{}`", name))?;
+            headers.push((header_name, template_name));
+        }
+
+        let body = match &self.body {
+            Some(body) if self.body_base64 => Some(MockBody::Raw(
+                base64::decode(body)
+                    .context("failed to decode the body")?
+                    .into(),
+            )),
+            Some(body) => {
+                tera.add_raw_template("body", body)
+                    .context("failed to parse the body template")?;
+                Some(MockBody::Template)
+            }
+            None => None,
+        };
+
+        Ok(MockResponse {
+            methods,
+            path,
+            match_headers,
+            status_code: self
+                .status_code
+                .try_into()
+                .context("failed to parse the status code")?,
+            tera,
+            headers,
+            body,
+            latency: Duration::from_millis(self.latency_ms),
+        })
+    }
+}
+
+enum Segment {
+    Literal(String),
+    Param(String),
+    Rest(String),
+}
+
+struct PathPattern {
+    segments: Vec<Segment>,
+}
+
+impl PathPattern {
+    fn parse(pattern: &str) -> Self {
+        let segments = pattern
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| {
+                if let Some(name) = segment.strip_prefix(':') {
+                    Segment::Param(name.to_string())
+                } else if let Some(name) = segment.strip_prefix('*') {
+                    Segment::Rest(name.to_string())
+                } else {
+                    Segment::Literal(segment.to_string())
+                }
+            })
+            .collect();
+        Self { segments }
+    }
+
+    fn matches(&self, path: &str) -> Option<HashMap<String, String>> {
+        let mut params = HashMap::new();
+        let mut path_segments = path.split('/').filter(|segment| !segment.is_empty());
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => {
+                    if path_segments.next()? != literal {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    params.insert(name.clone(), path_segments.next()?.to_string());
+                }
+                Segment::Rest(name) => {
+                    params.insert(name.clone(), path_segments.collect::<Vec<_>>().join("/"));
+                    return Some(params);
+                }
+            }
+        }
+
+        match path_segments.next() {
+            Some(_) => None,
+            None => Some(params),
+        }
+    }
+}
+
+enum MockBody {
+    Raw(Bytes),
+    Template,
+}
+
+struct MockResponse {
+    methods: Vec<Method>,
+    path: Option<PathPattern>,
+    match_headers: Vec<(HeaderName, String)>,
+    status_code: StatusCode,
+    tera: Tera,
+    headers: Vec<(HeaderName, String)>,
+    body: Option<MockBody>,
+    latency: Duration,
+}
+
+impl MockResponse {
+    /// Returns the path parameters if the response matches `req`.
+    fn matches(&self, req: &Request) -> Option<HashMap<String, String>> {
+        if !self.methods.is_empty() && !self.methods.contains(req.method()) {
+            return None;
+        }
+        let matches_headers = self.match_headers.iter().all(|(name, value)| {
+            req.headers()
+                .get_all(name)
+                .iter()
+                .any(|header_value| header_value.as_bytes() == value.as_bytes())
+        });
+        if !matches_headers {
+            return None;
+        }
+        match &self.path {
+            Some(path) => path.matches(req.uri().path()),
+            None => Some(HashMap::new()),
+        }
+    }
+}
+
+struct Mock {
+    responses: Vec<MockResponse>,
+    max_body_size: usize,
+}
+
+#[async_trait::async_trait]
+impl Endpoint for Mock {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> Self::Output {
+        let (response, params) = match self
+            .responses
+            .iter()
+            .find_map(|response| response.matches(&req).map(|params| (response, params)))
+        {
+            Some(res) => res,
+            None => return StatusCode::NOT_FOUND.into(),
+        };
+
+        let mut ctx = req
+            .extensions()
+            .get::<PluginContext>()
+            .cloned()
+            .unwrap_or_else(|| PluginContext::new(&req));
+        let mut body = Vec::new();
+        if let Err(err) = req
+            .take_body()
+            .into_async_read()
+            .take(self.max_body_size as u64 + 1)
+            .read_to_end(&mut body)
+            .await
+        {
+            warn!(error = %err, "failed to read the request body.");
+            return StatusCode::BAD_REQUEST.into();
+        }
+        if body.len() > self.max_body_size {
+            return StatusCode::PAYLOAD_TOO_LARGE.into();
+        }
+        let headers = req
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+            .collect::<HashMap<_, _>>();
+        let query = req
+            .uri()
+            .query()
+            .map(|query| {
+                serde_urlencoded::from_str::<HashMap<String, String>>(query).unwrap_or_default()
+            })
+            .unwrap_or_default();
+        ctx.insert("method", req.method().as_str());
+        ctx.insert("path", req.uri().path());
+        ctx.insert("query", &query);
+        ctx.insert("headers", &headers);
+        ctx.insert("params", &params);
+        ctx.insert("body", &String::from_utf8_lossy(&body));
+        if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&body) {
+            ctx.insert("json", &json);
+        }
+
+        if !response.latency.is_zero() {
+            tokio::time::sleep(response.latency).await;
+        }
+
+        let mut resp = Response::builder().status(response.status_code).finish();
+        for (name, template_name) in &response.headers {
+            let value = ctx.render_template(&response.tera, template_name);
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                resp.headers_mut().insert(name.clone(), value);
+            }
+        }
+        match &response.body {
+            Some(MockBody::Raw(body)) => resp.set_body(body.clone()),
+            Some(MockBody::Template) => resp.set_body(ctx.render_template(&response.tera, "body")),
+            None => {}
+        }
+        resp
+    }
+}
@@ -1,2 +1,7 @@
+mod aggregate;
 mod echo;
-mod upstream;
+mod fastcgi;
+mod mock;
+mod redirect;
+mod static_files;
+pub(crate) mod upstream;
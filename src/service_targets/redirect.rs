@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use poem::{
+    http::{header, StatusCode},
+    Endpoint, Request, Response,
+};
+use serde::{Deserialize, Serialize};
+use tera::Tera;
+
+use crate::{config::ServiceTargetConfig, plugins::PluginContext};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RedirectConfig {
+    #[serde(default = "default_status_code")]
+    status_code: u16,
+    /// The template of the `Location` header, with the variables of the
+    /// plugin context and `scheme`, `host`, `path` and `query` of the
+    /// original request uri, before the route prefix is stripped.
+    #[serde(default)]
+    location: Option<String>,
+    /// Redirects to the same url with `https` scheme.
+    #[serde(default)]
+    https: bool,
+    /// The port of the `https` url, omitted if it is 443.
+    #[serde(default = "default_https_port")]
+    https_port: u16,
+}
+
+const fn default_status_code() -> u16 {
+    302
+}
+
+const fn default_https_port() -> u16 {
+    443
+}
+
+#[typetag::serde(name = "redirect")]
+impl ServiceTargetConfig for RedirectConfig {
+    fn create(&self) -> Result<Arc<dyn Endpoint<Output = Response>>> {
+        let status = StatusCode::from_u16(self.status_code)
+            .ok()
+            .filter(|status| {
+                matches!(
+                    *status,
+                    StatusCode::MOVED_PERMANENTLY
+                        | StatusCode::FOUND
+                        | StatusCode::TEMPORARY_REDIRECT
+                        | StatusCode::PERMANENT_REDIRECT
+                )
+            })
+            .ok_or_else(|| anyhow!("invalid redirect status code `{}`", self.status_code))?;
+
+        let location = match (&self.location, self.https) {
+            (Some(location), false) => {
+                let mut tera = Tera::default();
+                tera.add_raw_template("location", location)
+                    .context("failed to parse the location template")?;
+                Location::Template(Box::new(tera))
+            }
+            (None, true) => Location::Https(self.https_port),
+            _ => bail!("exactly one of `location` and `https` must be specified"),
+        };
+
+        Ok(Arc::new(Redirect { status, location }))
+    }
+
+    fn uses_plugin_context(&self) -> bool {
+        self.location.is_some()
+    }
+}
+
+enum Location {
+    Template(Box<Tera>),
+    Https(u16),
+}
+
+struct Redirect {
+    status: StatusCode,
+    location: Location,
+}
+
+fn request_host(req: &Request) -> Option<&str> {
+    req.headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| req.uri().authority().map(|authority| authority.as_str()))
+}
+
+impl Redirect {
+    fn location(&self, req: &Request) -> Option<String> {
+        match &self.location {
+            Location::Template(tera) => {
+                let mut ctx = req
+                    .extensions()
+                    .get::<PluginContext>()
+                    .cloned()
+                    .unwrap_or_else(|| PluginContext::new(req));
+                let uri = req.original_uri();
+                ctx.insert("scheme", uri.scheme_str().unwrap_or("http"));
+                ctx.insert("host", request_host(req).unwrap_or_default());
+                ctx.insert("path", uri.path());
+                ctx.insert("query", uri.query().unwrap_or_default());
+                Some(ctx.render_template(tera, "location")).filter(|location| !location.is_empty())
+            }
+            Location::Https(port) => {
+                let host = request_host(req)?;
+                let host = match host.rsplit_once(':') {
+                    // keep the brackets of the IPv6 address
+                    Some((host, port)) if !port.contains(']') => host,
+                    _ => host,
+                };
+                let uri = req.original_uri();
+                let path_and_query = uri
+                    .path_and_query()
+                    .map(|path_and_query| path_and_query.as_str())
+                    .unwrap_or("/");
+                Some(match port {
+                    443 => format!("https://{}{}", host, path_and_query),
+                    port => format!("https://{}:{}{}", host, port, path_and_query),
+                })
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Endpoint for Redirect {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Self::Output {
+        match self.location(&req) {
+            Some(location) => Response::builder()
+                .status(self.status)
+                .header(header::LOCATION, location)
+                .finish(),
+            None => StatusCode::BAD_REQUEST.into(),
+        }
+    }
+}
@@ -0,0 +1,436 @@
+use std::{
+    fs::Metadata,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use poem::{
+    http::{header, HeaderValue, Method, StatusCode},
+    Body, Endpoint, Request, Response,
+};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::config::ServiceTargetConfig;
+
+/// The characters that are percent-encoded in the links of the directory
+/// listing.
+const LINK_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// The precompressed variants, in the order of preference.
+const ENCODINGS: &[(&str, &str)] = &[("br", "br"), ("gzip", "gz")];
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StaticConfig {
+    dir: String,
+    #[serde(default = "default_index_files")]
+    index_files: Vec<String>,
+    #[serde(default)]
+    listing: bool,
+    /// Serves `<file>.br` or `<file>.gz` if they exist and are accepted by
+    /// the client.
+    #[serde(default = "default_precompressed")]
+    precompressed: bool,
+    /// Serves the first index file of the root directory if the file is not
+    /// found, for single-page applications.
+    #[serde(default)]
+    spa_fallback: bool,
+}
+
+fn default_index_files() -> Vec<String> {
+    vec!["index.html".to_string()]
+}
+
+const fn default_precompressed() -> bool {
+    true
+}
+
+#[typetag::serde(name = "static")]
+impl ServiceTargetConfig for StaticConfig {
+    fn create(&self) -> Result<Arc<dyn Endpoint<Output = Response>>> {
+        let root = std::fs::canonicalize(&self.dir)
+            .with_context(|| format!("failed to open directory `{}`", self.dir))?;
+        anyhow::ensure!(root.is_dir(), "`{}` is not a directory", self.dir);
+
+        Ok(Arc::new(StaticFiles {
+            root,
+            index_files: self.index_files.clone(),
+            listing: self.listing,
+            precompressed: self.precompressed,
+            spa_fallback: self.spa_fallback,
+        }))
+    }
+}
+
+struct StaticFiles {
+    root: PathBuf,
+    index_files: Vec<String>,
+    listing: bool,
+    precompressed: bool,
+    spa_fallback: bool,
+}
+
+/// Resolves the request path to a path relative to the root directory,
+/// returns `None` if it tries to escape the root directory.
+fn relative_path(path: &str) -> Option<PathBuf> {
+    let path = percent_decode_str(path).decode_utf8().ok()?;
+    let mut relative_path = PathBuf::new();
+
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            _ if segment.contains('\\') || segment.contains('\0') => return None,
+            _ => relative_path.push(segment),
+        }
+    }
+
+    Some(relative_path)
+}
+
+/// Returns the metadata of `path`, or `None` if it does not exist or is a
+/// symbolic link that points outside the root directory.
+async fn metadata_in(root: &Path, path: &Path) -> Option<Metadata> {
+    let real_path = tokio::fs::canonicalize(path).await.ok()?;
+    if !real_path.starts_with(root) {
+        return None;
+    }
+    tokio::fs::metadata(real_path).await.ok()
+}
+
+impl StaticFiles {
+    async fn find_index(&self, dir: &Path) -> Option<(PathBuf, Metadata)> {
+        for index_file in &self.index_files {
+            let path = dir.join(index_file);
+            if let Some(metadata) = metadata_in(&self.root, &path).await {
+                if metadata.is_file() {
+                    return Some((path, metadata));
+                }
+            }
+        }
+        None
+    }
+
+    async fn serve(&self, req: &Request) -> Response {
+        let relative_path = match relative_path(req.uri().path()) {
+            Some(relative_path) => relative_path,
+            None => return StatusCode::FORBIDDEN.into(),
+        };
+        let path = self.root.join(relative_path);
+
+        match metadata_in(&self.root, &path).await {
+            Some(metadata) if metadata.is_file() => self.serve_file(req, &path, metadata).await,
+            Some(metadata) if metadata.is_dir() => {
+                if !req.uri().path().ends_with('/') {
+                    // a path starting with `//` is taken as another host
+                    let path = format!("/{}", req.original_uri().path().trim_start_matches('/'));
+                    let location = match req.uri().query() {
+                        Some(query) => format!("{}/?{}", path, query),
+                        None => format!("{}/", path),
+                    };
+                    return Response::builder()
+                        .status(StatusCode::MOVED_PERMANENTLY)
+                        .header(header::LOCATION, location)
+                        .finish();
+                }
+
+                if let Some((path, metadata)) = self.find_index(&path).await {
+                    self.serve_file(req, &path, metadata).await
+                } else if self.listing {
+                    list_dir(req, &path).await
+                } else {
+                    self.not_found(req).await
+                }
+            }
+            _ => self.not_found(req).await,
+        }
+    }
+
+    async fn not_found(&self, req: &Request) -> Response {
+        if self.spa_fallback {
+            if let Some((path, metadata)) = self.find_index(&self.root).await {
+                return self.serve_file(req, &path, metadata).await;
+            }
+        }
+        StatusCode::NOT_FOUND.into()
+    }
+
+    async fn serve_file(&self, req: &Request, path: &Path, metadata: Metadata) -> Response {
+        serve_file(req, &self.root, path, metadata, self.precompressed).await
+    }
+}
+
+fn accepts_encoding(req: &Request, encoding: &str) -> bool {
+    req.headers()
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| {
+            let mut parts = value.split(';');
+            let name = parts.next().unwrap_or_default().trim();
+            let rejected = parts.any(|param| {
+                param
+                    .trim()
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .map(|q| q == 0.0)
+                    .unwrap_or_default()
+            });
+            name.eq_ignore_ascii_case(encoding) && !rejected
+        })
+}
+
+/// Parses the `Range` header, returns `Err` if it is not satisfiable and
+/// `Ok(None)` if it should be ignored.
+fn parse_range(range: &str, len: u64) -> Result<Option<(u64, u64)>, ()> {
+    let range = match range.trim().strip_prefix("bytes=") {
+        Some(range) if !range.contains(',') => range.trim(),
+        _ => return Ok(None),
+    };
+    let (start, end) = match range.split_once('-') {
+        Some(res) => res,
+        None => return Ok(None),
+    };
+
+    let (start, end) = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+        (Ok(start), Err(_)) if end.is_empty() => (start, len.saturating_sub(1)),
+        (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => {
+            (len.saturating_sub(suffix), len.saturating_sub(1))
+        }
+        _ => return Ok(None),
+    };
+
+    if start >= len {
+        return Err(());
+    }
+    Ok(Some((start, end)))
+}
+
+fn etag_matches(header_value: &str, etag: &str) -> bool {
+    header_value.split(',').any(|value| {
+        let value = value.trim();
+        value == "*" || value.trim_start_matches("W/") == etag.trim_start_matches("W/")
+    })
+}
+
+async fn serve_file(
+    req: &Request,
+    root: &Path,
+    path: &Path,
+    metadata: Metadata,
+    precompressed: bool,
+) -> Response {
+    let content_type = mime_guess::from_path(path).first_or_octet_stream();
+
+    // use the precompressed variant if it exists
+    let mut file_path = path.to_path_buf();
+    let mut metadata = metadata;
+    let mut content_encoding = None;
+    if precompressed {
+        for (encoding, ext) in ENCODINGS {
+            if !accepts_encoding(req, encoding) {
+                continue;
+            }
+            let mut variant_path = path.as_os_str().to_owned();
+            variant_path.push(".");
+            variant_path.push(ext);
+            if let Some(variant_metadata) = metadata_in(root, Path::new(&variant_path)).await {
+                if variant_metadata.is_file() {
+                    file_path = variant_path.into();
+                    metadata = variant_metadata;
+                    content_encoding = Some(*encoding);
+                    break;
+                }
+            }
+        }
+    }
+
+    let len = metadata.len();
+    let modified = metadata.modified().ok();
+    let mtime = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let etag = match content_encoding {
+        Some(encoding) => format!("\"{:x}-{:x}-{}\"", len, mtime, encoding),
+        None => format!("\"{:x}-{:x}\"", len, mtime),
+    };
+    let last_modified = modified.map(httpdate::fmt_http_date);
+
+    let mut builder = Response::builder()
+        .header(header::ETAG, etag.as_str())
+        .header(header::ACCEPT_RANGES, "bytes")
+        .content_type(content_type.as_ref());
+    if let Some(last_modified) = &last_modified {
+        builder = builder.header(header::LAST_MODIFIED, last_modified.as_str());
+    }
+    if precompressed {
+        builder = builder.header(header::VARY, "accept-encoding");
+    }
+    if let Some(encoding) = content_encoding {
+        builder = builder.header(header::CONTENT_ENCODING, encoding);
+    }
+
+    // conditional requests
+    let header_str = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+    };
+    let not_modified = match header_str(header::IF_NONE_MATCH) {
+        Some(if_none_match) => etag_matches(if_none_match, &etag),
+        None => match (header_str(header::IF_MODIFIED_SINCE), modified) {
+            (Some(since), Some(modified)) => httpdate::parse_http_date(since)
+                .map(|since| truncate_to_secs(modified) <= since)
+                .unwrap_or_default(),
+            _ => false,
+        },
+    };
+    if not_modified {
+        return builder.status(StatusCode::NOT_MODIFIED).finish();
+    }
+
+    // a range request is ignored if the `If-Range` does not match
+    let if_range = match header_str(header::IF_RANGE) {
+        Some(if_range) if if_range.starts_with('"') || if_range.starts_with("W/") => {
+            etag_matches(if_range, &etag)
+        }
+        Some(if_range) => last_modified.as_deref() == Some(if_range),
+        None => true,
+    };
+    let range = match header_str(header::RANGE) {
+        Some(range) if if_range => match parse_range(range, len) {
+            Ok(range) => range,
+            Err(()) => {
+                return builder
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+                    .finish();
+            }
+        },
+        _ => None,
+    };
+
+    let (start, body_len) = match range {
+        Some((start, end)) => {
+            builder = builder.status(StatusCode::PARTIAL_CONTENT).header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, len),
+            );
+            (start, end - start + 1)
+        }
+        None => (0, len),
+    };
+    builder = builder.header(header::CONTENT_LENGTH, body_len);
+
+    if req.method() == Method::HEAD {
+        return builder.finish();
+    }
+
+    let mut file = match tokio::fs::File::open(&file_path).await {
+        Ok(file) => file,
+        Err(err) => {
+            error!(path = %file_path.display(), error = %err, "failed to open file");
+            return StatusCode::INTERNAL_SERVER_ERROR.into();
+        }
+    };
+    if start > 0 {
+        if let Err(err) = file.seek(SeekFrom::Start(start)).await {
+            error!(path = %file_path.display(), error = %err, "failed to seek file");
+            return StatusCode::INTERNAL_SERVER_ERROR.into();
+        }
+    }
+
+    builder.body(Body::from_async_read(file.take(body_len)))
+}
+
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| UNIX_EPOCH + std::time::Duration::from_secs(duration.as_secs()))
+        .unwrap_or(time)
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+async fn list_dir(req: &Request, path: &Path) -> Response {
+    let mut entries = Vec::new();
+    let mut read_dir = match tokio::fs::read_dir(path).await {
+        Ok(read_dir) => read_dir,
+        Err(err) => {
+            error!(path = %path.display(), error = %err, "failed to read directory");
+            return StatusCode::INTERNAL_SERVER_ERROR.into();
+        }
+    };
+    while let Ok(Some(entry)) = read_dir.next_entry().await {
+        let is_dir = entry
+            .file_type()
+            .await
+            .map(|file_type| file_type.is_dir())
+            .unwrap_or_default();
+        let mut name = entry.file_name().to_string_lossy().into_owned();
+        if is_dir {
+            name.push('/');
+        }
+        entries.push(name);
+    }
+    entries.sort();
+
+    let title = escape_html(&percent_decode_str(req.original_uri().path()).decode_utf8_lossy());
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>Index of {0}</title></head>\n<body>\n<h1>Index of {0}</h1>\n<ul>\n<li><a href=\"../\">../</a></li>\n",
+        title
+    );
+    for name in entries {
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            utf8_percent_encode(&name, LINK_ENCODE_SET),
+            escape_html(&name)
+        ));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+
+    let resp = Response::builder().content_type("text/html; charset=utf-8");
+    if req.method() == Method::HEAD {
+        resp.finish()
+    } else {
+        resp.body(html)
+    }
+}
+
+#[async_trait::async_trait]
+impl Endpoint for StaticFiles {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Self::Output {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header(header::ALLOW, "GET, HEAD")
+                .finish();
+        }
+
+        self.serve(&req).await
+    }
+}
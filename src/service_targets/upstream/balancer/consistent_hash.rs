@@ -0,0 +1,98 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use poem::Request;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    plugins::ConsumerName,
+    service_targets::upstream::{
+        balancer::{Balancer, BalancerConfig},
+        node::Node,
+    },
+};
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+enum HashKey {
+    RemoteIp,
+    Header(String),
+    Cookie(String),
+    ConsumerName,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConsistentHashConfig {
+    key: HashKey,
+}
+
+#[typetag::serde(name = "consistentHash")]
+impl BalancerConfig for ConsistentHashConfig {
+    fn create(&self) -> Result<Box<dyn Balancer>> {
+        Ok(Box::new(ConsistentHash {
+            key: self.key.clone(),
+        }))
+    }
+}
+
+/// Weighted rendezvous hashing, only the requests that were mapped to a
+/// removed node are remapped when the node list changes.
+struct ConsistentHash {
+    key: HashKey,
+}
+
+impl ConsistentHash {
+    fn hash_key(&self, req: &Request) -> Option<String> {
+        match &self.key {
+            HashKey::RemoteIp => None,
+            HashKey::Header(name) => req
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok().map(ToString::to_string)),
+            HashKey::Cookie(name) => req
+                .cookie()
+                .get(name)
+                .map(|cookie| cookie.value_str().to_string()),
+            HashKey::ConsumerName => req
+                .extensions()
+                .get::<ConsumerName>()
+                .map(|name| name.0.clone()),
+        }
+    }
+}
+
+fn score(key: &str, node: &Node) -> f64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    node.authority.hash(&mut hasher);
+    let hash = (hasher.finish() as f64 + 1.0) / (u64::MAX as f64 + 2.0);
+    f64::from(node.weight) / -hash.ln()
+}
+
+impl Balancer for ConsistentHash {
+    fn select(&self, nodes: &[Arc<Node>], req: &Request) -> Arc<Node> {
+        // fallback to the remote address if the key is missing
+        let key = self.hash_key(req).unwrap_or_else(|| {
+            req.remote_addr()
+                .as_socket_addr()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_default()
+        });
+
+        let mut best = &nodes[0];
+        let mut best_score = score(&key, best);
+        for node in &nodes[1..] {
+            let score = score(&key, node);
+            if score > best_score {
+                best = node;
+                best_score = score;
+            }
+        }
+        best.clone()
+    }
+}
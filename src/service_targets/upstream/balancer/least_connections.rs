@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use poem::Request;
+use serde::{Deserialize, Serialize};
+
+use crate::service_targets::upstream::{
+    balancer::{Balancer, BalancerConfig},
+    node::Node,
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LeastConnectionsConfig {}
+
+#[typetag::serde(name = "leastConnections")]
+impl BalancerConfig for LeastConnectionsConfig {
+    fn create(&self) -> Result<Box<dyn Balancer>> {
+        Ok(Box::new(LeastConnections))
+    }
+}
+
+/// Selects the node with the fewest in-flight requests relative to its weight.
+struct LeastConnections;
+
+impl Balancer for LeastConnections {
+    fn select(&self, nodes: &[Arc<Node>], _req: &Request) -> Arc<Node> {
+        let mut best = &nodes[0];
+        for node in &nodes[1..] {
+            // compare `connections / weight` without dividing
+            let lhs = node.connections() as u64 * u64::from(best.weight);
+            let rhs = best.connections() as u64 * u64::from(node.weight);
+            if lhs < rhs {
+                best = node;
+            }
+        }
+        best.clone()
+    }
+}
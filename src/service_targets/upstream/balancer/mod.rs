@@ -0,0 +1,23 @@
+mod consistent_hash;
+mod least_connections;
+mod random;
+mod round_robin;
+mod weighted_round_robin;
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use poem::Request;
+
+pub use crate::service_targets::upstream::balancer::round_robin::RoundRobinConfig;
+use crate::service_targets::upstream::node::Node;
+
+#[typetag::serde(tag = "type")]
+pub trait BalancerConfig: Send + Sync + 'static {
+    fn create(&self) -> Result<Box<dyn Balancer>>;
+}
+
+pub trait Balancer: Send + Sync + 'static {
+    /// Selects a node for the request, `nodes` is never empty.
+    fn select(&self, nodes: &[Arc<Node>], req: &Request) -> Arc<Node>;
+}
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use poem::Request;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::service_targets::upstream::{
+    balancer::{Balancer, BalancerConfig},
+    node::Node,
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RandomConfig {}
+
+#[typetag::serde(name = "random")]
+impl BalancerConfig for RandomConfig {
+    fn create(&self) -> Result<Box<dyn Balancer>> {
+        Ok(Box::new(Random))
+    }
+}
+
+/// Selects a random node, nodes with a higher weight are selected more often.
+struct Random;
+
+impl Balancer for Random {
+    fn select(&self, nodes: &[Arc<Node>], _req: &Request) -> Arc<Node> {
+        let total: u64 = nodes.iter().map(|node| u64::from(node.weight)).sum();
+        let mut n = rand::thread_rng().gen_range(0..total);
+
+        for node in nodes {
+            if n < u64::from(node.weight) {
+                return node.clone();
+            }
+            n -= u64::from(node.weight);
+        }
+        nodes[nodes.len() - 1].clone()
+    }
+}
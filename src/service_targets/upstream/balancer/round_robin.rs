@@ -0,0 +1,37 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use anyhow::Result;
+use poem::Request;
+use serde::{Deserialize, Serialize};
+
+use crate::service_targets::upstream::{
+    balancer::{Balancer, BalancerConfig},
+    node::Node,
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoundRobinConfig {}
+
+#[typetag::serde(name = "roundRobin")]
+impl BalancerConfig for RoundRobinConfig {
+    fn create(&self) -> Result<Box<dyn Balancer>> {
+        Ok(Box::new(RoundRobin {
+            next: AtomicUsize::new(0),
+        }))
+    }
+}
+
+struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl Balancer for RoundRobin {
+    fn select(&self, nodes: &[Arc<Node>], _req: &Request) -> Arc<Node> {
+        let idx = self.next.fetch_add(1, Ordering::Relaxed);
+        nodes[idx % nodes.len()].clone()
+    }
+}
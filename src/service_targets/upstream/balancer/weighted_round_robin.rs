@@ -0,0 +1,55 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use poem::{http::uri::Authority, Request};
+use serde::{Deserialize, Serialize};
+
+use crate::service_targets::upstream::{
+    balancer::{Balancer, BalancerConfig},
+    node::Node,
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WeightedRoundRobinConfig {}
+
+#[typetag::serde(name = "weightedRoundRobin")]
+impl BalancerConfig for WeightedRoundRobinConfig {
+    fn create(&self) -> Result<Box<dyn Balancer>> {
+        Ok(Box::new(WeightedRoundRobin {
+            current_weights: Default::default(),
+        }))
+    }
+}
+
+/// Smooth weighted round-robin, the same algorithm as Nginx.
+struct WeightedRoundRobin {
+    current_weights: Mutex<HashMap<Authority, i64>>,
+}
+
+impl Balancer for WeightedRoundRobin {
+    fn select(&self, nodes: &[Arc<Node>], _req: &Request) -> Arc<Node> {
+        let mut current_weights = self.current_weights.lock();
+        let total: i64 = nodes.iter().map(|node| i64::from(node.weight)).sum();
+
+        // forget the nodes that are no longer selectable
+        current_weights
+            .retain(|authority, _| nodes.iter().any(|node| &node.authority == authority));
+
+        let mut best: Option<(&Arc<Node>, i64)> = None;
+        for node in nodes {
+            let current = current_weights.entry(node.authority.clone()).or_insert(0);
+            *current += i64::from(node.weight);
+            if best.map(|(_, weight)| *current > weight).unwrap_or(true) {
+                best = Some((node, *current));
+            }
+        }
+
+        let (node, _) = best.unwrap();
+        if let Some(current) = current_weights.get_mut(&node.authority) {
+            *current -= total;
+        }
+        node.clone()
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use poem::http::uri::Authority;
+use reqwest::{redirect::Policy, Client, ClientBuilder, Proxy};
+use rustls::Session;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::TlsConnector;
+use webpki::DNSNameRef;
+
+use crate::service_targets::upstream::{
+    node::Node,
+    tls::{Tls, TlsConfig},
+    UpstreamProtocol, UpstreamScheme,
+};
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProxyConfig {
+    url: String,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientConfig {
+    #[serde(default)]
+    connect_timeout_ms: Option<u64>,
+    /// Timeout of waiting for the response headers and each chunk of the
+    /// response body.
+    #[serde(default)]
+    read_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pool_idle_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pool_max_idle_per_host: Option<usize>,
+    #[serde(default)]
+    tcp_keepalive_ms: Option<u64>,
+    #[serde(default)]
+    http2_prior_knowledge: bool,
+    #[serde(default)]
+    proxy: Option<ProxyConfig>,
+}
+
+impl ClientConfig {
+    pub fn has_proxy(&self) -> bool {
+        self.proxy.is_some()
+    }
+
+    pub fn create(
+        &self,
+        scheme: UpstreamScheme,
+        protocol: UpstreamProtocol,
+        tls: &TlsConfig,
+    ) -> Result<UpstreamClient> {
+        anyhow::ensure!(
+            !self.http2_prior_knowledge || protocol == UpstreamProtocol::Auto,
+            "`http2PriorKnowledge` conflicts with protocol, use `h2c` instead"
+        );
+
+        let tls = tls.load()?;
+        let server_name = match scheme {
+            UpstreamScheme::Https => tls.server_name().map(ToString::to_string),
+            UpstreamScheme::Http => None,
+        };
+
+        let tls_connectors = match scheme {
+            UpstreamScheme::Https => Some((
+                TlsConnector::from(Arc::new(tls.rustls_config(&[b"http/1.1"])?)),
+                TlsConnector::from(Arc::new(tls.rustls_config(&[b"h2"])?)),
+            )),
+            UpstreamScheme::Http => None,
+        };
+
+        Ok(UpstreamClient {
+            default_client: self.builder(&tls, protocol)?.build()?,
+            tls_connectors,
+            config: self.clone(),
+            tls,
+            scheme,
+            protocol,
+            server_name,
+            node_clients: Default::default(),
+        })
+    }
+
+    fn builder(&self, tls: &Tls, protocol: UpstreamProtocol) -> Result<ClientBuilder> {
+        // the responses are forwarded as they are, so redirects and content
+        // decoding are left to the downstream client.
+        let mut builder = Client::builder()
+            .redirect(Policy::none())
+            .no_gzip()
+            .no_brotli()
+            .no_deflate()
+            .no_proxy();
+
+        if let Some(timeout) = self.connect_timeout_ms {
+            builder = builder.connect_timeout(Duration::from_millis(timeout));
+        }
+        if let Some(timeout) = self.pool_idle_timeout_ms {
+            builder = builder.pool_idle_timeout(Duration::from_millis(timeout));
+        }
+        if let Some(max) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if let Some(keepalive) = self.tcp_keepalive_ms {
+            builder = builder.tcp_keepalive(Duration::from_millis(keepalive));
+        }
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        if protocol == UpstreamProtocol::Http1 {
+            builder = builder.http1_only();
+        }
+        if let Some(proxy) = &self.proxy {
+            let mut p = Proxy::all(&proxy.url)
+                .with_context(|| format!("invalid proxy url `{}`", proxy.url))?;
+            if let Some(username) = &proxy.username {
+                p = p.basic_auth(username, proxy.password.as_deref().unwrap_or_default());
+            }
+            builder = builder.proxy(p);
+        }
+
+        Ok(tls.apply(builder))
+    }
+}
+
+/// The time after which the address of a node is resolved again when the
+/// server name is overridden.
+const RESOLVE_TTL: Duration = Duration::from_secs(60);
+
+pub trait Io: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static> Io for T {}
+
+/// The application protocol negotiated on the raw connections.
+#[derive(Copy, Clone)]
+pub enum Alpn {
+    Http1,
+    H2,
+}
+
+pub struct UpstreamClient {
+    /// The TLS connectors for HTTP/1.1 and HTTP/2.
+    tls_connectors: Option<(TlsConnector, TlsConnector)>,
+    config: ClientConfig,
+    tls: Tls,
+    scheme: UpstreamScheme,
+    protocol: UpstreamProtocol,
+    server_name: Option<String>,
+    default_client: Client,
+    node_clients: Mutex<HashMap<Authority, NodeClient>>,
+}
+
+/// The client that resolves the server name to the address of a node.
+struct NodeClient {
+    client: Client,
+    /// The entry is removed when the node is removed from the upstream.
+    node: Weak<Node>,
+    addr: SocketAddr,
+    resolved_at: Instant,
+}
+
+impl UpstreamClient {
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.config.read_timeout_ms.map(Duration::from_millis)
+    }
+
+    /// Returns the client and the authority of the url used to send requests
+    /// to `node`.
+    ///
+    /// If the server name is overridden, the url uses the server name for SNI
+    /// and certificate verification, and the client resolves it to the
+    /// address of the node.
+    pub async fn prepare(&self, node: &Arc<Node>) -> Result<(Client, Authority)> {
+        let server_name = match &self.server_name {
+            Some(server_name) => server_name,
+            None => return Ok((self.default_client.clone(), node.authority.clone())),
+        };
+
+        let port = node.port(self.scheme);
+        let authority: Authority = format!("{}:{}", server_name, port)
+            .parse()
+            .with_context(|| format!("invalid server name `{}`", server_name))?;
+
+        if let Some(node_client) = self.node_clients.lock().get(&node.authority) {
+            if node_client.resolved_at.elapsed() < RESOLVE_TTL
+                && Weak::ptr_eq(&node_client.node, &Arc::downgrade(node))
+            {
+                return Ok((node_client.client.clone(), authority));
+            }
+        }
+
+        let addr = tokio::net::lookup_host((node.host(), port))
+            .await?
+            .next()
+            .ok_or_else(|| anyhow!("failed to resolve `{}`", node.authority))?;
+
+        let mut node_clients = self.node_clients.lock();
+        // keep the connection pool if the address is not changed
+        let client = match node_clients.get(&node.authority) {
+            Some(node_client) if node_client.addr == addr => node_client.client.clone(),
+            _ => self
+                .config
+                .builder(&self.tls, self.protocol)?
+                .resolve(server_name, addr)
+                .build()?,
+        };
+        node_clients.retain(|_, node_client| node_client.node.strong_count() > 0);
+        node_clients.insert(
+            node.authority.clone(),
+            NodeClient {
+                client: client.clone(),
+                node: Arc::downgrade(node),
+                addr,
+                resolved_at: Instant::now(),
+            },
+        );
+        Ok((client, authority))
+    }
+
+    /// Opens a raw connection to `node` for the protocols that are not
+    /// handled by the http client.
+    pub async fn connect(&self, node: &Node, alpn: Alpn) -> Result<Box<dyn Io>> {
+        let connect = TcpStream::connect((node.host(), node.port(self.scheme)));
+        let stream = match self.config.connect_timeout_ms {
+            Some(timeout) => tokio::time::timeout(Duration::from_millis(timeout), connect)
+                .await
+                .map_err(|_| anyhow!("connect to `{}` timed out", node.authority))??,
+            None => connect.await?,
+        };
+
+        match &self.tls_connectors {
+            Some((http1, h2)) => {
+                let tls_connector = match alpn {
+                    Alpn::Http1 => http1,
+                    Alpn::H2 => h2,
+                };
+                let server_name = self.server_name.as_deref().unwrap_or_else(|| node.host());
+                let dns_name = DNSNameRef::try_from_ascii_str(server_name)
+                    .map_err(|_| anyhow!("invalid server name `{}`", server_name))?;
+                let stream = tls_connector.connect(dns_name, stream).await?;
+                if matches!(alpn, Alpn::H2) {
+                    anyhow::ensure!(
+                        stream.get_ref().1.get_alpn_protocol() == Some(b"h2"),
+                        "`{}` does not support HTTP/2",
+                        node.authority
+                    );
+                }
+                Ok(Box::new(stream))
+            }
+            None => Ok(Box::new(stream)),
+        }
+    }
+}
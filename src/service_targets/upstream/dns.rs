@@ -0,0 +1,186 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use poem::http::uri::Authority;
+use serde::{Deserialize, Serialize};
+use trust_dns_resolver::{
+    config::{LookupIpStrategy, NameServerConfig, Protocol, ResolverConfig, ResolverOpts},
+    error::{ResolveError, ResolveErrorKind},
+    TokioAsyncResolver,
+};
+
+use crate::service_targets::upstream::node::NodeList;
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DnsConfig {
+    /// The name to resolve, such as `_http._tcp.example.com` for SRV records.
+    name: String,
+    /// Resolves SRV records instead of A/AAAA records.
+    #[serde(default)]
+    srv: bool,
+    /// The port of the nodes resolved from A/AAAA records, defaults to the
+    /// port of the scheme for HTTP upstreams.
+    #[serde(default)]
+    port: Option<u16>,
+    /// The minimum interval in seconds between resolutions, regardless of
+    /// the TTL of the records.
+    #[serde(default = "default_min_ttl")]
+    min_ttl: u64,
+    /// The name servers to query, defaults to the system configuration.
+    #[serde(default)]
+    nameservers: Vec<SocketAddr>,
+}
+
+const fn default_min_ttl() -> u64 {
+    5
+}
+
+impl DnsConfig {
+    /// Spawns a task that keeps the discovered nodes of `nodes` up to date,
+    /// the task is aborted when the nodes are dropped.
+    pub fn spawn(&self, default_port: Option<u16>, nodes: &Arc<NodeList>) -> Result<()> {
+        let port = match (self.port.or(default_port), self.srv) {
+            (Some(port), _) => port,
+            (None, true) => 0,
+            (None, false) => bail!("the port of `{}` is required", self.name),
+        };
+
+        let opts = ResolverOpts {
+            ip_strategy: LookupIpStrategy::Ipv4AndIpv6,
+            ..Default::default()
+        };
+
+        let resolver = if self.nameservers.is_empty() {
+            let (config, _) = trust_dns_resolver::system_conf::read_system_conf()
+                .context("failed to read the system DNS configuration")?;
+            TokioAsyncResolver::tokio(config, opts)?
+        } else {
+            let mut config = ResolverConfig::new();
+            for addr in &self.nameservers {
+                for protocol in [Protocol::Udp, Protocol::Tcp] {
+                    config.add_name_server(NameServerConfig {
+                        socket_addr: *addr,
+                        protocol,
+                        tls_dns_name: None,
+                        trust_nx_responses: true,
+                    });
+                }
+            }
+            TokioAsyncResolver::tokio(config, opts)?
+        };
+
+        let resolver = DnsResolver {
+            resolver,
+            name: self.name.clone(),
+            srv: self.srv,
+            port,
+            min_ttl: Duration::from_secs(self.min_ttl),
+        };
+        nodes.add_task(tokio::spawn(resolver.run(Arc::downgrade(nodes))));
+        Ok(())
+    }
+}
+
+struct DnsResolver {
+    resolver: TokioAsyncResolver,
+    name: String,
+    srv: bool,
+    port: u16,
+    min_ttl: Duration,
+}
+
+fn make_authority(ip: IpAddr, port: u16) -> Option<Authority> {
+    SocketAddr::new(ip, port).to_string().parse().ok()
+}
+
+fn is_no_records(err: &ResolveError) -> bool {
+    matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. })
+}
+
+impl DnsResolver {
+    async fn run(self, nodes: Weak<NodeList>) {
+        loop {
+            let res = self.resolve().await;
+            let nodes = match nodes.upgrade() {
+                Some(nodes) => nodes,
+                None => break,
+            };
+
+            let ttl = match res {
+                Ok((discovered, valid_until)) => {
+                    nodes.update(discovered);
+                    valid_until.saturating_duration_since(Instant::now())
+                }
+                Err(err) if is_no_records(&err) => {
+                    warn!(name = %self.name, "no DNS records found for the upstream.");
+                    nodes.update(Vec::new());
+                    self.min_ttl
+                }
+                Err(err) => {
+                    // keep the last known nodes
+                    warn!(name = %self.name, error = %err, "failed to resolve the upstream.");
+                    self.min_ttl
+                }
+            };
+
+            drop(nodes);
+            tokio::time::sleep(ttl.max(self.min_ttl)).await;
+        }
+    }
+
+    /// Returns the discovered nodes and the time until which they are valid.
+    async fn resolve(&self) -> Result<(Vec<(Authority, u32)>, Instant), ResolveError> {
+        if !self.srv {
+            let lookup = self.resolver.lookup_ip(self.name.as_str()).await?;
+            let nodes = lookup
+                .iter()
+                .filter_map(|ip| make_authority(ip, self.port))
+                .map(|authority| (authority, 1))
+                .collect();
+            return Ok((nodes, lookup.valid_until()));
+        }
+
+        let lookup = self.resolver.srv_lookup(self.name.as_str()).await?;
+        let mut valid_until = lookup.as_lookup().valid_until();
+
+        // only the targets with the lowest priority are used
+        let priority = lookup.iter().map(|srv| srv.priority()).min();
+        let mut ips = HashMap::new();
+        let mut nodes = Vec::new();
+
+        for srv in lookup.iter().filter(|srv| Some(srv.priority()) == priority) {
+            let target = srv.target().to_utf8();
+            if !ips.contains_key(&target) {
+                let target_ips = match self.resolver.lookup_ip(srv.target().clone()).await {
+                    Ok(lookup) => {
+                        valid_until = valid_until.min(lookup.valid_until());
+                        lookup.iter().collect()
+                    }
+                    Err(err) => {
+                        warn!(target = %target, error = %err, "failed to resolve the SRV target.");
+                        Vec::new()
+                    }
+                };
+                ips.insert(target.clone(), target_ips);
+            }
+
+            // a weight of 0 means the target is rarely selected
+            let weight = u32::from(srv.weight()).max(1);
+            for ip in &ips[&target] {
+                if let Some(authority) = make_authority(*ip, srv.port()) {
+                    if !nodes.iter().any(|(existing, _)| existing == &authority) {
+                        nodes.push((authority, weight));
+                    }
+                }
+            }
+        }
+
+        Ok((nodes, valid_until))
+    }
+}
@@ -0,0 +1,208 @@
+use std::sync::Arc;
+
+use hyper::body::HttpBody;
+use poem::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    Request, Response,
+};
+
+use crate::{
+    plugins::StreamingBody,
+    service_targets::upstream::{
+        headers::remove_hop_by_hop_headers,
+        http2::StreamGuard,
+        node::{Node, NodeGuard},
+        retry::AttemptError,
+        Upstream,
+    },
+};
+
+const GRPC_STATUS: &str = "grpc-status";
+const GRPC_MESSAGE: &str = "grpc-message";
+
+/// The gRPC status codes used by the gateway.
+#[derive(Copy, Clone)]
+enum Code {
+    Unknown = 2,
+    DeadlineExceeded = 4,
+    PermissionDenied = 7,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    Unauthenticated = 16,
+}
+
+impl Code {
+    /// Maps the HTTP status of a response without `grpc-status`, as described
+    /// in `doc/http-grpc-status-mapping.md` of gRPC.
+    fn from_http_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::BAD_REQUEST => Code::Internal,
+            StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+            StatusCode::FORBIDDEN => Code::PermissionDenied,
+            StatusCode::NOT_FOUND => Code::Unimplemented,
+            StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT => Code::Unavailable,
+            _ => Code::Unknown,
+        }
+    }
+}
+
+pub fn is_grpc_request(req: &Request) -> bool {
+    req.content_type()
+        .map(|content_type| content_type.starts_with("application/grpc"))
+        .unwrap_or_default()
+}
+
+fn status_headers(code: Code, message: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(GRPC_STATUS, HeaderValue::from(code as u16));
+    if let Ok(message) = HeaderValue::from_str(message) {
+        headers.insert(GRPC_MESSAGE, message);
+    }
+    headers
+}
+
+/// Creates a trailers-only response with the status `code`.
+fn error_response(code: Code, message: &str) -> Response {
+    let mut resp = Response::builder()
+        .header(header::CONTENT_TYPE, "application/grpc")
+        .finish();
+    resp.headers_mut().extend(status_headers(code, message));
+    resp
+}
+
+impl Upstream {
+    pub(crate) async fn proxy_grpc(&self, req: Request) -> Response {
+        let node = match self.select_node(&req, &[]) {
+            Some(node) => node,
+            None => return error_response(Code::Unavailable, "no upstream nodes"),
+        };
+        let guard = node.acquire();
+
+        match self.send_grpc(&node, req).await {
+            Ok((resp, stream_guard)) => {
+                self.report(&node, Some(resp.status().as_u16()));
+                self.make_grpc_response(resp, guard, stream_guard)
+            }
+            Err(err) => {
+                self.report(&node, None);
+                error!(node = %node.authority, error = %err, "upstream grpc error");
+                match err {
+                    AttemptError::Timeout => {
+                        error_response(Code::DeadlineExceeded, "upstream timed out")
+                    }
+                    AttemptError::Connect(_) => {
+                        error_response(Code::Unavailable, "upstream unavailable")
+                    }
+                    AttemptError::Other(_) => error_response(Code::Internal, "upstream error"),
+                }
+            }
+        }
+    }
+
+    async fn send_grpc(
+        &self,
+        node: &Arc<Node>,
+        mut req: Request,
+    ) -> Result<(hyper::Response<hyper::Body>, StreamGuard), AttemptError> {
+        let mut headers = self.header_policy.request_headers(&req);
+        headers.insert(header::TE, HeaderValue::from_static("trailers"));
+        let body = req.take_body().into();
+        let upstream_req = self.make_http2_request(node, &req, headers, body)?;
+        info!(node = %node.authority, uri = %upstream_req.uri(), "forward grpc to upstream");
+
+        let (resp, stream_guard) = self.http2.send(&self.client, node, upstream_req).await?;
+        let timeout = match (self.per_try_timeout, self.read_timeout) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let resp = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, resp)
+                .await
+                .map_err(|_| AttemptError::Timeout)?
+                .map_err(|err| AttemptError::Other(err.into()))?,
+            None => resp.await.map_err(|err| AttemptError::Other(err.into()))?,
+        };
+        Ok((resp, stream_guard))
+    }
+
+    fn make_grpc_response(
+        &self,
+        resp: hyper::Response<hyper::Body>,
+        guard: NodeGuard,
+        stream_guard: StreamGuard,
+    ) -> Response {
+        let (parts, mut upstream_body) = resp.into_parts();
+
+        if !parts.headers.contains_key(GRPC_STATUS) && parts.status != StatusCode::OK {
+            return error_response(
+                Code::from_http_status(parts.status),
+                &format!("upstream responded with status {}", parts.status.as_u16()),
+            );
+        }
+
+        // the body is copied by a task so that the trailers are preserved
+        let (mut sender, body) = hyper::Body::channel();
+        let read_timeout = self.read_timeout;
+        tokio::spawn(async move {
+            let _guard = (guard, stream_guard);
+
+            loop {
+                let data = match read_timeout {
+                    Some(timeout) => {
+                        match tokio::time::timeout(timeout, upstream_body.data()).await {
+                            Ok(data) => data,
+                            Err(_) => {
+                                let _ = sender
+                                    .send_trailers(status_headers(
+                                        Code::Unavailable,
+                                        "read upstream response timed out",
+                                    ))
+                                    .await;
+                                return;
+                            }
+                        }
+                    }
+                    None => upstream_body.data().await,
+                };
+
+                match data {
+                    Some(Ok(data)) => {
+                        if sender.send_data(data).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Err(err)) => {
+                        warn!(error = %err, "failed to read the upstream grpc response");
+                        let _ = sender
+                            .send_trailers(status_headers(Code::Unavailable, "upstream reset"))
+                            .await;
+                        return;
+                    }
+                    None => break,
+                }
+            }
+
+            match upstream_body.trailers().await {
+                Ok(Some(trailers)) => {
+                    let _ = sender.send_trailers(trailers).await;
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    warn!(error = %err, "failed to read the upstream grpc trailers");
+                    let _ = sender
+                        .send_trailers(status_headers(Code::Unavailable, "upstream reset"))
+                        .await;
+                }
+            }
+        });
+
+        let mut resp: Response = hyper::Response::from_parts(parts, body).into();
+        remove_hop_by_hop_headers(resp.headers_mut());
+        resp.extensions_mut().insert(StreamingBody);
+        resp
+    }
+}
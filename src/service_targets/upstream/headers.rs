@@ -0,0 +1,197 @@
+use std::net::IpAddr;
+
+use anyhow::{Context, Result};
+use cidr::IpCidr;
+use poem::{
+    http::{header, header::HeaderName, HeaderMap, HeaderValue},
+    Request,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::config::ListenerScheme;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+const X_FORWARDED_PORT: &str = "x-forwarded-port";
+const FORWARDED: &str = "forwarded";
+
+/// Hop-by-hop headers defined in RFC 7230, section 6.1.
+const HOP_BY_HOP_HEADERS: &[HeaderName] = &[
+    header::CONNECTION,
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForwardedConfig {
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+    /// The proxies whose forwarding headers are trusted and appended to,
+    /// the forwarding headers from other clients are replaced.
+    #[serde(default)]
+    trusted_proxies: Vec<IpCidr>,
+}
+
+impl Default for ForwardedConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+const fn default_enabled() -> bool {
+    true
+}
+
+pub struct HeaderPolicy {
+    pass_host: bool,
+    rewrite_host: Option<HeaderValue>,
+    forwarded: bool,
+    trusted_proxies: Vec<IpCidr>,
+}
+
+impl HeaderPolicy {
+    pub fn new(
+        pass_host: bool,
+        rewrite_host: Option<&str>,
+        forwarded: &ForwardedConfig,
+    ) -> Result<Self> {
+        Ok(Self {
+            pass_host,
+            rewrite_host: match rewrite_host {
+                Some(host) => Some(
+                    HeaderValue::from_str(host)
+                        .with_context(|| format!("invalid rewrite host `{}`", host))?,
+                ),
+                None => None,
+            },
+            forwarded: forwarded.enabled,
+            trusted_proxies: forwarded.trusted_proxies.clone(),
+        })
+    }
+
+    /// Returns the headers sent to the upstream for `req`.
+    pub fn request_headers(&self, req: &Request) -> HeaderMap {
+        let mut headers = req.headers().clone();
+        remove_hop_by_hop_headers(&mut headers);
+
+        let host = headers.remove(header::HOST);
+        if let Some(rewrite_host) = &self.rewrite_host {
+            headers.insert(header::HOST, rewrite_host.clone());
+        } else if self.pass_host {
+            if let Some(host) = &host {
+                headers.insert(header::HOST, host.clone());
+            }
+        }
+
+        if self.forwarded {
+            self.add_forwarded_headers(req, host.as_ref(), &mut headers);
+        }
+
+        headers
+    }
+
+    fn add_forwarded_headers(
+        &self,
+        req: &Request,
+        host: Option<&HeaderValue>,
+        headers: &mut HeaderMap,
+    ) {
+        let remote_ip = req.remote_addr().as_socket_addr().map(|addr| addr.ip());
+        let trusted = remote_ip
+            .map(|ip| self.trusted_proxies.iter().any(|cidr| cidr.contains(&ip)))
+            .unwrap_or_default();
+
+        if !trusted {
+            headers.remove(X_FORWARDED_FOR);
+            headers.remove(X_FORWARDED_PROTO);
+            headers.remove(X_FORWARDED_HOST);
+            headers.remove(X_FORWARDED_PORT);
+            headers.remove(FORWARDED);
+        }
+
+        let proto = req
+            .extensions()
+            .get::<ListenerScheme>()
+            .map(|scheme| scheme.0)
+            .unwrap_or("http");
+        let host = host.and_then(|host| host.to_str().ok());
+        let port = host
+            .and_then(|host| host.parse::<poem::http::uri::Authority>().ok())
+            .and_then(|authority| authority.port_u16())
+            .unwrap_or(if proto == "https" { 443 } else { 80 });
+
+        if let Some(ip) = remote_ip {
+            append_value(headers, X_FORWARDED_FOR, &ip.to_string());
+        }
+        if !headers.contains_key(X_FORWARDED_PROTO) {
+            set_value(headers, X_FORWARDED_PROTO, proto);
+        }
+        if let Some(host) = host {
+            if !headers.contains_key(X_FORWARDED_HOST) {
+                set_value(headers, X_FORWARDED_HOST, host);
+            }
+        }
+        if !headers.contains_key(X_FORWARDED_PORT) {
+            set_value(headers, X_FORWARDED_PORT, &port.to_string());
+        }
+
+        let mut forwarded = match remote_ip {
+            Some(IpAddr::V4(ip)) => format!("for={}", ip),
+            Some(IpAddr::V6(ip)) => format!("for=\"[{}]\"", ip),
+            None => "for=unknown".to_string(),
+        };
+        if let Some(host) = host {
+            forwarded.push_str(&format!(";host=\"{}\"", host));
+        }
+        forwarded.push_str(&format!(";proto={}", proto));
+        append_value(headers, FORWARDED, &forwarded);
+    }
+}
+
+fn set_value(headers: &mut HeaderMap, name: &'static str, value: &str) {
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(name, value);
+    }
+}
+
+/// Appends `value` to the comma separated list of header `name`.
+fn append_value(headers: &mut HeaderMap, name: &'static str, value: &str) {
+    let values = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .chain(std::iter::once(value))
+        .collect::<Vec<_>>()
+        .join(", ");
+    set_value(headers, name, &values);
+}
+
+/// Removes the hop-by-hop headers, including the headers listed in the
+/// `Connection` header.
+pub fn remove_hop_by_hop_headers(headers: &mut HeaderMap) {
+    let connection_headers = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| name.trim().parse::<HeaderName>().ok())
+        .collect::<Vec<_>>();
+
+    for name in connection_headers {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP_HEADERS {
+        headers.remove(name);
+    }
+    headers.remove("keep-alive");
+    headers.remove("proxy-connection");
+}
@@ -0,0 +1,306 @@
+use std::{
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::service_targets::upstream::{client::UpstreamClient, node::Node, UpstreamScheme};
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthCheckConfig {
+    #[serde(default)]
+    pub active: Option<ActiveHealthCheckConfig>,
+    #[serde(default)]
+    pub passive: Option<PassiveHealthCheckConfig>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusRange {
+    from: u16,
+    to: u16,
+}
+
+impl Default for StatusRange {
+    fn default() -> Self {
+        Self { from: 200, to: 399 }
+    }
+}
+
+impl StatusRange {
+    fn server_errors() -> Self {
+        Self { from: 500, to: 599 }
+    }
+
+    fn check(&self) -> Result<()> {
+        anyhow::ensure!(
+            self.from <= self.to,
+            "invalid status range `{}-{}`",
+            self.from,
+            self.to
+        );
+        Ok(())
+    }
+
+    fn contains(&self, status: u16) -> bool {
+        (self.from..=self.to).contains(&status)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Probe {
+    #[serde(rename_all = "camelCase")]
+    Http {
+        #[serde(default = "default_path")]
+        path: String,
+        #[serde(default)]
+        expected_status: StatusRange,
+    },
+    Tcp,
+}
+
+fn default_path() -> String {
+    "/".to_string()
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveHealthCheckConfig {
+    probe: Probe,
+    #[serde(default = "default_interval")]
+    interval: u64,
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+    #[serde(default = "default_healthy_threshold")]
+    healthy_threshold: u32,
+    #[serde(default = "default_unhealthy_threshold")]
+    unhealthy_threshold: u32,
+}
+
+const fn default_interval() -> u64 {
+    5
+}
+
+const fn default_timeout() -> u64 {
+    1
+}
+
+const fn default_healthy_threshold() -> u32 {
+    2
+}
+
+const fn default_unhealthy_threshold() -> u32 {
+    3
+}
+
+impl ActiveHealthCheckConfig {
+    pub fn create(
+        &self,
+        scheme: UpstreamScheme,
+        client: &Arc<UpstreamClient>,
+    ) -> Result<ActiveChecker> {
+        if let Probe::Http {
+            expected_status, ..
+        } = &self.probe
+        {
+            expected_status.check()?;
+        }
+        anyhow::ensure!(
+            self.healthy_threshold > 0 && self.unhealthy_threshold > 0,
+            "the health check thresholds must be greater than 0"
+        );
+        anyhow::ensure!(
+            self.interval > 0 && self.timeout > 0,
+            "the health check interval and timeout must be greater than 0"
+        );
+
+        Ok(ActiveChecker {
+            probe: self.probe.clone(),
+            scheme,
+            client: client.clone(),
+            interval: Duration::from_secs(self.interval),
+            timeout: Duration::from_secs(self.timeout),
+            healthy_threshold: self.healthy_threshold,
+            unhealthy_threshold: self.unhealthy_threshold,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct ActiveChecker {
+    probe: Probe,
+    scheme: UpstreamScheme,
+    client: Arc<UpstreamClient>,
+    interval: Duration,
+    timeout: Duration,
+    healthy_threshold: u32,
+    unhealthy_threshold: u32,
+}
+
+impl ActiveChecker {
+    /// Spawns a probe task for `node`, the task exits when the node is
+    /// dropped.
+    pub fn spawn(&self, node: &Arc<Node>) {
+        tokio::spawn(self.clone().run(Arc::downgrade(node)));
+    }
+
+    async fn run(self, node: Weak<Node>) {
+        let mut successes = 0;
+        let mut failures = 0;
+
+        loop {
+            let node = match node.upgrade() {
+                Some(node) => node,
+                None => break,
+            };
+
+            let res = tokio::time::timeout(self.timeout, self.probe(&node))
+                .await
+                .unwrap_or_else(|_| Err(anyhow!("timed out")));
+
+            match res {
+                Ok(()) => {
+                    successes += 1;
+                    failures = 0;
+                    if !node.is_healthy() && successes >= self.healthy_threshold {
+                        node.set_healthy(true);
+                        info!(node = %node.authority, "upstream node is healthy.");
+                    }
+                }
+                Err(err) => {
+                    failures += 1;
+                    successes = 0;
+                    debug!(node = %node.authority, error = %err, "health check failed.");
+                    if node.is_healthy() && failures >= self.unhealthy_threshold {
+                        node.set_healthy(false);
+                        warn!(node = %node.authority, error = %err, "upstream node is unhealthy.");
+                    }
+                }
+            }
+
+            drop(node);
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+
+    async fn probe(&self, node: &Arc<Node>) -> Result<()> {
+        match &self.probe {
+            Probe::Http {
+                path,
+                expected_status,
+            } => {
+                let scheme = match self.scheme {
+                    UpstreamScheme::Http => "http",
+                    UpstreamScheme::Https => "https",
+                };
+                let (client, authority) = self.client.prepare(node).await?;
+                let resp = client
+                    .get(format!("{}://{}{}", scheme, authority, path))
+                    .send()
+                    .await?;
+                let status = resp.status().as_u16();
+                anyhow::ensure!(
+                    expected_status.contains(status),
+                    "unexpected status code `{}`",
+                    status
+                );
+                Ok(())
+            }
+            Probe::Tcp => {
+                tokio::net::TcpStream::connect((node.host(), node.port(self.scheme))).await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PassiveHealthCheckConfig {
+    #[serde(default = "default_consecutive_errors")]
+    consecutive_errors: u32,
+    #[serde(default = "StatusRange::server_errors")]
+    error_status: StatusRange,
+    #[serde(default = "default_ejection_time")]
+    ejection_time: u64,
+    /// The maximum percentage of the nodes that can be ejected at the same
+    /// time, one node can always be ejected unless it is 0.
+    #[serde(default = "default_max_ejection_percent")]
+    max_ejection_percent: u32,
+}
+
+const fn default_consecutive_errors() -> u32 {
+    5
+}
+
+const fn default_ejection_time() -> u64 {
+    30
+}
+
+const fn default_max_ejection_percent() -> u32 {
+    50
+}
+
+impl PassiveHealthCheckConfig {
+    pub fn create(&self) -> Result<PassiveChecker> {
+        self.error_status.check()?;
+        anyhow::ensure!(
+            self.consecutive_errors > 0,
+            "`consecutiveErrors` must be greater than 0"
+        );
+        anyhow::ensure!(
+            self.max_ejection_percent <= 100,
+            "invalid max ejection percent `{}`",
+            self.max_ejection_percent
+        );
+
+        Ok(PassiveChecker {
+            consecutive_errors: self.consecutive_errors,
+            error_status: self.error_status,
+            ejection_time: Duration::from_secs(self.ejection_time),
+            max_ejection_percent: self.max_ejection_percent,
+        })
+    }
+}
+
+/// Ejects nodes for a while based on the results of the proxied requests.
+pub struct PassiveChecker {
+    consecutive_errors: u32,
+    error_status: StatusRange,
+    ejection_time: Duration,
+    max_ejection_percent: u32,
+}
+
+impl PassiveChecker {
+    /// Reports the result of a request to `node`, `status` is `None` if the
+    /// request failed without a response.
+    pub fn report(&self, nodes: &[Arc<Node>], node: &Node, status: Option<u16>) {
+        let success = matches!(status, Some(status) if !self.error_status.contains(status));
+        if node.record_result(success) < self.consecutive_errors || node.is_ejected() {
+            return;
+        }
+
+        let ejected = nodes.iter().filter(|node| node.is_ejected()).count();
+        let allowed = (nodes.len() * self.max_ejection_percent as usize / 100)
+            .max((self.max_ejection_percent > 0) as usize);
+        if ejected >= allowed {
+            debug!(
+                node = %node.authority,
+                ejected = ejected,
+                "too many upstream nodes are ejected, skip ejecting."
+            );
+            return;
+        }
+
+        node.eject(Instant::now() + self.ejection_time);
+        warn!(
+            node = %node.authority,
+            ejection_time = self.ejection_time.as_secs(),
+            "upstream node is ejected."
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+};
+
+use futures_util::StreamExt;
+use hyper::client::conn::{ResponseFuture, SendRequest};
+use parking_lot::Mutex;
+use poem::{
+    http::{header, uri::Authority, HeaderMap, Uri, Version},
+    Request,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::service_targets::upstream::{
+    client::{Alpn, UpstreamClient},
+    node::Node,
+    retry::AttemptError,
+    Upstream, UpstreamScheme,
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Http2Config {
+    /// The maximum number of connections to each node.
+    #[serde(default = "default_max_connections")]
+    pub max_connections: usize,
+    /// A new connection is opened when all the connections to a node have
+    /// this many in-flight requests, until `maxConnections` is reached.
+    #[serde(default = "default_max_concurrent_streams")]
+    pub max_concurrent_streams: usize,
+}
+
+impl Default for Http2Config {
+    fn default() -> Self {
+        Self {
+            max_connections: default_max_connections(),
+            max_concurrent_streams: default_max_concurrent_streams(),
+        }
+    }
+}
+
+const fn default_max_connections() -> usize {
+    4
+}
+
+const fn default_max_concurrent_streams() -> usize {
+    100
+}
+
+/// Counts a request as in-flight on its connection until it is dropped.
+pub struct StreamGuard(Arc<AtomicUsize>);
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The sender of an HTTP/2 connection, the requests must wait until the
+/// connection is ready before they are sent.
+type Sender = Arc<tokio::sync::Mutex<SendRequest<hyper::Body>>>;
+
+#[derive(Clone)]
+struct Connection {
+    sender: Sender,
+    streams: Arc<AtomicUsize>,
+    /// Set when the connection is closed.
+    closed: Arc<AtomicBool>,
+}
+
+impl Connection {
+    /// Sends the request once the connection is ready, or gives it back if
+    /// the connection is closed.
+    async fn send(
+        &self,
+        req: hyper::Request<hyper::Body>,
+    ) -> Result<(ResponseFuture, StreamGuard), hyper::Request<hyper::Body>> {
+        self.streams.fetch_add(1, Ordering::Relaxed);
+        let guard = StreamGuard(self.streams.clone());
+
+        let mut sender = self.sender.lock().await;
+        if futures_util::future::poll_fn(|cx| sender.poll_ready(cx))
+            .await
+            .is_err()
+        {
+            self.closed.store(true, Ordering::Relaxed);
+            return Err(req);
+        }
+        Ok((sender.send_request(req), guard))
+    }
+}
+
+/// The connections to a node, they are closed when the node is removed from
+/// the upstream.
+struct NodeConnections {
+    node: Weak<Node>,
+    connections: Vec<Connection>,
+}
+
+/// The multiplexed HTTP/2 connections to the upstream nodes.
+pub struct Http2Pool {
+    max_connections: usize,
+    max_concurrent_streams: usize,
+    connections: Mutex<HashMap<Authority, NodeConnections>>,
+}
+
+impl Http2Pool {
+    pub fn new(config: &Http2Config) -> Self {
+        Self {
+            max_connections: config.max_connections.max(1),
+            max_concurrent_streams: config.max_concurrent_streams.max(1),
+            connections: Default::default(),
+        }
+    }
+
+    /// Returns the least busy connection, or `None` if a new connection
+    /// should be opened.
+    fn select(&self, node: &Arc<Node>) -> Option<Connection> {
+        let mut connections = self.connections.lock();
+        let node_connections = connections.get_mut(&node.authority)?;
+        if !Weak::ptr_eq(&node_connections.node, &Arc::downgrade(node)) {
+            return None;
+        }
+        let connections = &mut node_connections.connections;
+        connections.retain(|conn| !conn.closed.load(Ordering::Relaxed));
+
+        let has_capacity = connections.len() < self.max_connections;
+        let conn = connections
+            .iter()
+            .min_by_key(|conn| conn.streams.load(Ordering::Relaxed))?;
+        if has_capacity && conn.streams.load(Ordering::Relaxed) >= self.max_concurrent_streams {
+            return None;
+        }
+        Some(conn.clone())
+    }
+
+    pub async fn send(
+        &self,
+        client: &UpstreamClient,
+        node: &Arc<Node>,
+        mut req: hyper::Request<hyper::Body>,
+    ) -> Result<(ResponseFuture, StreamGuard), AttemptError> {
+        if let Some(conn) = self.select(node) {
+            match conn.send(req).await {
+                Ok(res) => return Ok(res),
+                Err(returned) => req = returned,
+            }
+        }
+
+        let io = client
+            .connect(node, Alpn::H2)
+            .await
+            .map_err(AttemptError::Connect)?;
+        let (sender, conn) = hyper::client::conn::Builder::new()
+            .http2_only(true)
+            .handshake::<_, hyper::Body>(io)
+            .await
+            .map_err(|err| AttemptError::Connect(err.into()))?;
+        let closed = Arc::new(AtomicBool::new(false));
+        tokio::spawn({
+            let authority = node.authority.clone();
+            let closed = closed.clone();
+            async move {
+                if let Err(err) = conn.await {
+                    debug!(node = %authority, error = %err, "http2 connection closed.");
+                }
+                closed.store(true, Ordering::Relaxed);
+            }
+        });
+
+        let conn = Connection {
+            sender: Arc::new(tokio::sync::Mutex::new(sender)),
+            streams: Default::default(),
+            closed,
+        };
+        let res = conn
+            .send(req)
+            .await
+            .map_err(|_| AttemptError::Connect(anyhow!("the connection is closed")))?;
+
+        // the connections opened concurrently beyond the limit are closed
+        // once their requests are completed
+        let mut connections = self.connections.lock();
+        connections.retain(|_, node_connections| node_connections.node.strong_count() > 0);
+        let node_connections = connections
+            .entry(node.authority.clone())
+            .or_insert_with(|| NodeConnections {
+                node: Arc::downgrade(node),
+                connections: Vec::new(),
+            });
+        if !Weak::ptr_eq(&node_connections.node, &Arc::downgrade(node)) {
+            // the node is replaced, such as its weight is changed
+            *node_connections = NodeConnections {
+                node: Arc::downgrade(node),
+                connections: Vec::new(),
+            };
+        }
+        if node_connections.connections.len() < self.max_connections {
+            node_connections.connections.push(conn);
+        }
+        Ok(res)
+    }
+}
+
+impl Upstream {
+    /// Creates the request sent to `node` over HTTP/2, the authority of the
+    /// uri is taken from the `Host` header.
+    pub(crate) fn make_http2_request(
+        &self,
+        node: &Node,
+        req: &Request,
+        mut headers: HeaderMap,
+        body: hyper::Body,
+    ) -> Result<hyper::Request<hyper::Body>, AttemptError> {
+        let authority = match headers.remove(header::HOST) {
+            Some(host) => host
+                .to_str()
+                .ok()
+                .and_then(|host| host.parse::<Authority>().ok())
+                .unwrap_or_else(|| node.authority.clone()),
+            None => node.authority.clone(),
+        };
+
+        let mut uri_parts = req.uri().clone().into_parts();
+        uri_parts.scheme = match self.scheme {
+            UpstreamScheme::Http => Some(poem::http::uri::Scheme::HTTP),
+            UpstreamScheme::Https => Some(poem::http::uri::Scheme::HTTPS),
+        };
+        uri_parts.authority = Some(authority);
+        let uri = Uri::from_parts(uri_parts).map_err(|err| AttemptError::Other(err.into()))?;
+
+        let mut upstream_req = hyper::Request::new(body);
+        *upstream_req.method_mut() = req.method().clone();
+        *upstream_req.uri_mut() = uri;
+        *upstream_req.version_mut() = Version::HTTP_2;
+        *upstream_req.headers_mut() = headers;
+        Ok(upstream_req)
+    }
+
+    /// Sends the request over a multiplexed HTTP/2 connection, the response
+    /// holds the stream until its body is consumed.
+    pub(crate) async fn send_http2(
+        &self,
+        node: &Arc<Node>,
+        req: &Request,
+        body: hyper::Body,
+    ) -> Result<reqwest::Response, AttemptError> {
+        let upstream_req =
+            self.make_http2_request(node, req, self.header_policy.request_headers(req), body)?;
+        info!(node = %node.authority, uri = %upstream_req.uri(), "forward to upstream");
+
+        let (resp, guard) = self.http2.send(&self.client, node, upstream_req).await?;
+        let resp = resp.await.map_err(|err| AttemptError::Other(err.into()))?;
+        let (parts, body) = resp.into_parts();
+        let body = body.map(move |data| {
+            let _ = &guard;
+            data
+        });
+        Ok(hyper::Response::from_parts(parts, reqwest::Body::wrap_stream(body)).into())
+    }
+}
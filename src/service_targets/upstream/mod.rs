@@ -0,0 +1,465 @@
+pub(crate) mod balancer;
+mod client;
+pub(crate) mod dns;
+mod grpc;
+mod headers;
+mod health_check;
+mod http2;
+pub(crate) mod node;
+mod retry;
+mod tls;
+mod unix;
+mod websocket;
+
+use std::{io::ErrorKind, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures_util::{stream::BoxStream, StreamExt, TryStreamExt};
+use poem::{
+    http::{header, StatusCode, Uri},
+    Body, Endpoint, Request, Response,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::ServiceTargetConfig,
+    discovery::DiscoveryConfig,
+    plugins::StreamingBody,
+    service_targets::upstream::{
+        balancer::{Balancer, BalancerConfig, RoundRobinConfig},
+        client::{ClientConfig, UpstreamClient},
+        dns::DnsConfig,
+        headers::{remove_hop_by_hop_headers, ForwardedConfig, HeaderPolicy},
+        health_check::{HealthCheckConfig, PassiveChecker},
+        http2::{Http2Config, Http2Pool},
+        node::{Node, NodeConfig, NodeGuard, NodeList},
+        retry::{AttemptError, ReplayBody, RetryConfig, RetryPolicy, TimeoutConfig},
+        tls::TlsConfig,
+        websocket::WebSocketConfig,
+    },
+};
+
+#[derive(Serialize, Deserialize, Copy, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum UpstreamScheme {
+    Http,
+    Https,
+}
+
+impl UpstreamScheme {
+    pub fn default_port(self) -> u16 {
+        match self {
+            UpstreamScheme::Http => 80,
+            UpstreamScheme::Https => 443,
+        }
+    }
+}
+
+/// The HTTP version used to forward the requests.
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum UpstreamProtocol {
+    /// HTTP/2 if it is negotiated with ALPN, otherwise HTTP/1.1.
+    #[default]
+    Auto,
+    Http1,
+    /// HTTP/2 negotiated with ALPN, requires the `https` scheme.
+    Http2,
+    /// HTTP/2 over cleartext with prior knowledge, requires the `http`
+    /// scheme.
+    H2c,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpstreamConfig {
+    scheme: UpstreamScheme,
+    #[serde(default)]
+    host: Option<String>,
+    #[serde(default)]
+    nodes: Vec<NodeConfig>,
+    /// Discovers the nodes by resolving a DNS name periodically, they are
+    /// used in addition to `host` and `nodes`. The `https` scheme requires
+    /// `tls.serverName`.
+    #[serde(default)]
+    dns: Option<DnsConfig>,
+    /// Discovers the nodes from a registry, they are used in addition to
+    /// `host` and `nodes`. Only one of `dns` and `discovery` can be
+    /// specified.
+    #[serde(default)]
+    discovery: Option<Box<dyn DiscoveryConfig>>,
+    #[serde(default = "default_balancer")]
+    balancer: Box<dyn BalancerConfig>,
+    #[serde(default)]
+    health_check: HealthCheckConfig,
+    #[serde(default)]
+    retry: Option<RetryConfig>,
+    #[serde(default)]
+    timeout: TimeoutConfig,
+    #[serde(default)]
+    protocol: UpstreamProtocol,
+    /// The limits of the multiplexed connections for `http2`, `h2c` and
+    /// gRPC.
+    #[serde(default)]
+    http2: Http2Config,
+    #[serde(default)]
+    client: ClientConfig,
+    #[serde(default)]
+    tls: TlsConfig,
+    #[serde(default = "default_pass_host")]
+    pass_host: bool,
+    #[serde(default)]
+    rewrite_host: Option<String>,
+    #[serde(default)]
+    forwarded: ForwardedConfig,
+    #[serde(default)]
+    websocket: WebSocketConfig,
+    /// Proxies the gRPC requests over HTTP/2, `h2c` for `http` scheme.
+    #[serde(default)]
+    grpc: bool,
+}
+
+const fn default_pass_host() -> bool {
+    true
+}
+
+pub(crate) fn default_balancer() -> Box<dyn BalancerConfig> {
+    Box::new(RoundRobinConfig {})
+}
+
+#[typetag::serde(name = "upstream")]
+impl ServiceTargetConfig for UpstreamConfig {
+    fn create(&self) -> Result<Arc<dyn Endpoint<Output = Response>>> {
+        let mut nodes = Vec::new();
+
+        if let Some(host) = &self.host {
+            nodes.push(
+                NodeConfig {
+                    host: host.clone(),
+                    weight: 1,
+                }
+                .create()?,
+            );
+        }
+        for node in &self.nodes {
+            nodes.push(node.create()?);
+        }
+        anyhow::ensure!(
+            !nodes.is_empty() || self.dns.is_some() || self.discovery.is_some(),
+            "at least one upstream node is required"
+        );
+        anyhow::ensure!(
+            self.dns.is_none() || self.discovery.is_none(),
+            "`dns` and `discovery` cannot be used together"
+        );
+        // the resolved nodes are IP addresses, which cannot be verified
+        // against the certificates
+        anyhow::ensure!(
+            self.dns.is_none()
+                || matches!(self.scheme, UpstreamScheme::Http)
+                || self.tls.has_server_name(),
+            "`tls.serverName` is required for `dns` with the `https` scheme"
+        );
+
+        match (self.protocol, self.scheme) {
+            (UpstreamProtocol::Http2, UpstreamScheme::Http) => {
+                bail!("protocol `http2` requires the `https` scheme, use `h2c` instead")
+            }
+            (UpstreamProtocol::H2c, UpstreamScheme::Https) => {
+                bail!("protocol `h2c` requires the `http` scheme, use `http2` instead")
+            }
+            _ => {}
+        }
+
+        // the multiplexed connections are opened without the http client
+        let multiplexed = self.grpc
+            || matches!(
+                self.protocol,
+                UpstreamProtocol::Http2 | UpstreamProtocol::H2c
+            );
+        anyhow::ensure!(
+            !multiplexed || !self.client.has_proxy(),
+            "`client.proxy` cannot be used with the `http2` and `h2c` protocols or gRPC"
+        );
+
+        let client = Arc::new(self.client.create(self.scheme, self.protocol, &self.tls)?);
+
+        let active_checker = match &self.health_check.active {
+            Some(active) => Some(active.create(self.scheme, &client)?),
+            None => None,
+        };
+        let nodes = Arc::new(NodeList::new(nodes, active_checker));
+        if let Some(dns) = &self.dns {
+            dns.spawn(Some(self.scheme.default_port()), &nodes)?;
+        }
+        if let Some(discovery) = &self.discovery {
+            nodes.watch(discovery.watch()?);
+        }
+
+        Ok(Arc::new(Upstream {
+            scheme: self.scheme,
+            read_timeout: client.read_timeout(),
+            client,
+            header_policy: HeaderPolicy::new(
+                self.pass_host,
+                self.rewrite_host.as_deref(),
+                &self.forwarded,
+            )?,
+            nodes,
+            balancer: self.balancer.create()?,
+            passive_checker: match &self.health_check.passive {
+                Some(passive) => Some(passive.create()?),
+                None => None,
+            },
+            retry: match &self.retry {
+                Some(retry) => Some(retry.create()?),
+                None => None,
+            },
+            per_try_timeout: self.timeout.per_try(),
+            total_timeout: self.timeout.total(),
+            websocket: self.websocket.enabled,
+            websocket_idle_timeout: Duration::from_secs(self.websocket.idle_timeout),
+            protocol: self.protocol,
+            http2: Http2Pool::new(&self.http2),
+            grpc: self.grpc,
+        }))
+    }
+}
+
+struct Upstream {
+    scheme: UpstreamScheme,
+    client: Arc<UpstreamClient>,
+    header_policy: HeaderPolicy,
+    nodes: Arc<NodeList>,
+    balancer: Box<dyn Balancer>,
+    passive_checker: Option<PassiveChecker>,
+    retry: Option<RetryPolicy>,
+    per_try_timeout: Option<Duration>,
+    total_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    websocket: bool,
+    websocket_idle_timeout: Duration,
+    protocol: UpstreamProtocol,
+    http2: Http2Pool,
+    grpc: bool,
+}
+
+impl Upstream {
+    fn report(&self, node: &Node, status: Option<u16>) {
+        if let Some(passive_checker) = &self.passive_checker {
+            passive_checker.report(&self.nodes.get(), node, status);
+        }
+    }
+
+    fn select_node(&self, req: &Request, tried: &[Arc<Node>]) -> Option<Arc<Node>> {
+        self.nodes.select(&*self.balancer, req, tried)
+    }
+
+    async fn send(
+        &self,
+        node: &Arc<Node>,
+        req: &Request,
+        body: hyper::Body,
+    ) -> Result<(reqwest::Response, NodeGuard), AttemptError> {
+        let guard = node.acquire();
+        let timeout = match (self.per_try_timeout, self.read_timeout) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let res = match self.protocol {
+            UpstreamProtocol::Http2 | UpstreamProtocol::H2c => match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, self.send_http2(node, req, body))
+                    .await
+                    .unwrap_or(Err(AttemptError::Timeout)),
+                None => self.send_http2(node, req, body).await,
+            },
+            UpstreamProtocol::Auto | UpstreamProtocol::Http1 => {
+                self.send_http1(node, req, body, timeout).await
+            }
+        };
+
+        match res {
+            Ok(resp) => {
+                self.report(node, Some(resp.status().as_u16()));
+                Ok((resp, guard))
+            }
+            Err(err) => {
+                self.report(node, None);
+                Err(err)
+            }
+        }
+    }
+
+    /// Sends the request with the http client, which negotiates the HTTP
+    /// version unless `http1` is specified.
+    async fn send_http1(
+        &self,
+        node: &Arc<Node>,
+        req: &Request,
+        body: hyper::Body,
+        timeout: Option<Duration>,
+    ) -> Result<reqwest::Response, AttemptError> {
+        let (client, authority) = self
+            .client
+            .prepare(node)
+            .await
+            .map_err(AttemptError::Connect)?;
+        let mut uri_parts = req.uri().clone().into_parts();
+
+        uri_parts.scheme = match self.scheme {
+            UpstreamScheme::Http => Some(poem::http::uri::Scheme::HTTP),
+            UpstreamScheme::Https => Some(poem::http::uri::Scheme::HTTPS),
+        };
+        uri_parts.authority = Some(authority);
+
+        let new_uri = Uri::from_parts(uri_parts).unwrap().to_string();
+        info!(uri = %new_uri, "forward to upstream");
+
+        let mut upstream_req =
+            reqwest::Request::new(req.method().clone(), new_uri.parse().unwrap());
+        *upstream_req.headers_mut() = self.header_policy.request_headers(req);
+        *upstream_req.body_mut() = Some(body.into());
+
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, client.execute(upstream_req))
+                .await
+                .map(|res| res.map_err(Into::into))
+                .unwrap_or(Err(AttemptError::Timeout)),
+            None => client.execute(upstream_req).await.map_err(Into::into),
+        }
+    }
+
+    async fn forward(&self, mut req: Request) -> Response {
+        let body = req.take_body();
+        let (mut max_attempts, mut body) = match &self.retry {
+            Some(retry) => {
+                let max_attempts = retry.max_attempts(req.method());
+                if max_attempts > 1 {
+                    match ReplayBody::new(body, retry.max_body_size()).await {
+                        Ok(body) => (max_attempts, body),
+                        Err(err) => {
+                            error!(error = %err, "failed to read the request body");
+                            return StatusCode::BAD_REQUEST.into();
+                        }
+                    }
+                } else {
+                    (1, ReplayBody::Stream(Some(body)))
+                }
+            }
+            None => (1, ReplayBody::Stream(Some(body))),
+        };
+        if !body.is_replayable() {
+            max_attempts = 1;
+        }
+
+        let mut tried = Vec::new();
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+            let node = match self.select_node(&req, &tried) {
+                Some(node) => node,
+                None => return StatusCode::SERVICE_UNAVAILABLE.into(),
+            };
+            let is_last = attempt >= max_attempts;
+
+            match (self.send(&node, &req, body.take()).await, &self.retry) {
+                (Ok((resp, _)), Some(retry))
+                    if !is_last && retry.should_retry_status(resp.status()) =>
+                {
+                    warn!(
+                        node = %node.authority,
+                        status = %resp.status(),
+                        attempt = attempt,
+                        "upstream response is retryable.",
+                    );
+                }
+                (Ok((resp, guard)), _) => return self.make_response(resp, guard),
+                (Err(err), Some(retry)) if !is_last && retry.should_retry_error(&err) => {
+                    warn!(
+                        node = %node.authority,
+                        error = %err,
+                        attempt = attempt,
+                        "upstream request is retryable.",
+                    );
+                }
+                (Err(err), _) => {
+                    error!(
+                        node = %node.authority,
+                        error = %err,
+                        "upstream error",
+                    );
+                    let status = match err {
+                        AttemptError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+                        _ => StatusCode::SERVICE_UNAVAILABLE,
+                    };
+                    return Response::builder().status(status).finish();
+                }
+            }
+
+            tried.push(node);
+            if let Some(retry) = &self.retry {
+                tokio::time::sleep(retry.backoff(attempt)).await;
+            }
+        }
+    }
+
+    fn make_response(&self, mut resp: reqwest::Response, guard: NodeGuard) -> Response {
+        let mut new_resp = Response::default();
+        new_resp.set_status(resp.status());
+        std::mem::swap(new_resp.headers_mut(), resp.headers_mut());
+        remove_hop_by_hop_headers(new_resp.headers_mut());
+        if !new_resp.headers().contains_key(header::CONTENT_LENGTH) {
+            new_resp.extensions_mut().insert(StreamingBody);
+        }
+
+        let stream = resp.bytes_stream().map_err(move |err| {
+            // the node is in use until the response body is consumed
+            let _ = &guard;
+            std::io::Error::new(ErrorKind::Other, err)
+        });
+        let stream: BoxStream<'static, std::io::Result<Bytes>> = match self.read_timeout {
+            Some(read_timeout) => tokio_stream::StreamExt::timeout(stream, read_timeout)
+                .map(|res| {
+                    res.unwrap_or_else(|_| {
+                        Err(std::io::Error::new(
+                            ErrorKind::TimedOut,
+                            "read upstream response timed out",
+                        ))
+                    })
+                })
+                .boxed(),
+            None => stream.boxed(),
+        };
+
+        // each chunk is sent to the client as soon as it is received
+        new_resp.set_body(Body::from(hyper::Body::wrap_stream(stream)));
+        new_resp
+    }
+}
+
+#[async_trait::async_trait]
+impl Endpoint for Upstream {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Self::Output {
+        if self.websocket && websocket::is_upgrade_request(&req) {
+            return self.proxy_websocket(req).await;
+        }
+        if self.grpc && grpc::is_grpc_request(&req) {
+            return self.proxy_grpc(req).await;
+        }
+
+        match self.total_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, self.forward(req)).await {
+                Ok(resp) => resp,
+                Err(_) => {
+                    error!("upstream request timed out");
+                    StatusCode::GATEWAY_TIMEOUT.into()
+                }
+            },
+            None => self.forward(req).await,
+        }
+    }
+}
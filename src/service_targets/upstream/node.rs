@@ -0,0 +1,293 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use futures_util::{stream::BoxStream, StreamExt};
+use parking_lot::{Mutex, RwLock};
+use poem::{http::uri::Authority, Request};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::{
+    discovery::DiscoveredNode,
+    service_targets::upstream::{balancer::Balancer, health_check::ActiveChecker, UpstreamScheme},
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeConfig {
+    pub host: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+const fn default_weight() -> u32 {
+    1
+}
+
+impl NodeConfig {
+    pub fn create(&self) -> Result<Arc<Node>> {
+        let authority: Authority = self
+            .host
+            .parse()
+            .with_context(|| format!("failed to parse host `{}`", self.host))?;
+        anyhow::ensure!(
+            self.weight > 0,
+            "the weight of node `{}` must be greater than 0",
+            self.host
+        );
+        Ok(Arc::new(Node::new(authority, self.weight)))
+    }
+}
+
+pub struct Node {
+    pub authority: Authority,
+    pub weight: u32,
+    connections: AtomicUsize,
+    healthy: AtomicBool,
+    consecutive_errors: AtomicU32,
+    ejected_until: Mutex<Option<Instant>>,
+}
+
+impl Node {
+    pub fn new(authority: Authority, weight: u32) -> Self {
+        Self {
+            authority,
+            weight,
+            connections: AtomicUsize::new(0),
+            healthy: AtomicBool::new(true),
+            consecutive_errors: AtomicU32::new(0),
+            ejected_until: Mutex::new(None),
+        }
+    }
+
+    /// Returns the host of the node without the brackets of IPv6 addresses.
+    pub fn host(&self) -> &str {
+        self.authority
+            .host()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+    }
+
+    pub fn port(&self, scheme: UpstreamScheme) -> u16 {
+        self.authority
+            .port_u16()
+            .unwrap_or_else(|| scheme.default_port())
+    }
+
+    /// Returns `true` if the node is healthy and not ejected.
+    #[inline]
+    pub fn is_available(&self) -> bool {
+        self.is_healthy() && !self.is_ejected()
+    }
+
+    #[inline]
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn set_healthy(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::Relaxed);
+    }
+
+    pub fn is_ejected(&self) -> bool {
+        let mut ejected_until = self.ejected_until.lock();
+        match *ejected_until {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                *ejected_until = None;
+                info!(node = %self.authority, "upstream node is no longer ejected.");
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn eject(&self, until: Instant) {
+        *self.ejected_until.lock() = Some(until);
+        self.consecutive_errors.store(0, Ordering::Relaxed);
+    }
+
+    /// Records the result of a request and returns the number of consecutive
+    /// errors.
+    pub fn record_result(&self, success: bool) -> u32 {
+        if success {
+            self.consecutive_errors.store(0, Ordering::Relaxed);
+            0
+        } else {
+            self.consecutive_errors.fetch_add(1, Ordering::Relaxed) + 1
+        }
+    }
+
+    /// Returns the number of in-flight requests to this node.
+    #[inline]
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    /// Marks the start of a request, the returned guard marks the end of it
+    /// when dropped.
+    pub fn acquire(self: &Arc<Self>) -> NodeGuard {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        NodeGuard(self.clone())
+    }
+}
+
+pub struct NodeGuard(Arc<Node>);
+
+impl Drop for NodeGuard {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The nodes of an upstream, the configured nodes are fixed and the
+/// discovered nodes are replaced as a whole when they change.
+pub struct NodeList {
+    static_nodes: Vec<Arc<Node>>,
+    nodes: RwLock<Arc<Vec<Arc<Node>>>>,
+    active_checker: Option<ActiveChecker>,
+    /// The tasks updating the discovered nodes, aborted when the nodes are
+    /// dropped.
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Drop for NodeList {
+    fn drop(&mut self) {
+        for task in self.tasks.get_mut().drain(..) {
+            task.abort();
+        }
+    }
+}
+
+impl NodeList {
+    pub fn new(static_nodes: Vec<Arc<Node>>, active_checker: Option<ActiveChecker>) -> Self {
+        if let Some(active_checker) = &active_checker {
+            for node in &static_nodes {
+                active_checker.spawn(node);
+            }
+        }
+        Self {
+            nodes: RwLock::new(Arc::new(static_nodes.clone())),
+            static_nodes,
+            active_checker,
+            tasks: Default::default(),
+        }
+    }
+
+    /// Aborts the task when the nodes are dropped.
+    pub fn add_task(&self, task: JoinHandle<()>) {
+        self.tasks.lock().push(task);
+    }
+
+    /// Returns a snapshot of the current nodes.
+    #[inline]
+    pub fn get(&self) -> Arc<Vec<Arc<Node>>> {
+        self.nodes.read().clone()
+    }
+
+    /// Selects an available node, preferring the nodes that have not been
+    /// tried yet, returns `None` if there are no nodes.
+    pub fn select(
+        &self,
+        balancer: &dyn Balancer,
+        req: &Request,
+        tried: &[Arc<Node>],
+    ) -> Option<Arc<Node>> {
+        let nodes = self.get();
+        if nodes.is_empty() {
+            warn!("no upstream nodes.");
+            return None;
+        }
+
+        let available_nodes = nodes
+            .iter()
+            .filter(|node| node.is_available())
+            .cloned()
+            .collect::<Vec<_>>();
+        let untried_nodes = available_nodes
+            .iter()
+            .filter(|node| !tried.iter().any(|tried| Arc::ptr_eq(tried, node)))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        Some(if !untried_nodes.is_empty() {
+            balancer.select(&untried_nodes, req)
+        } else if !available_nodes.is_empty() {
+            balancer.select(&available_nodes, req)
+        } else {
+            // it is better to try an unavailable node than to reject all requests
+            warn!("all upstream nodes are unavailable.");
+            balancer.select(&nodes, req)
+        })
+    }
+
+    /// Replaces the discovered nodes, the nodes that are not changed keep
+    /// their states.
+    pub fn update(&self, discovered: Vec<(Authority, u32)>) {
+        let current = self.get();
+        let mut nodes = self.static_nodes.clone();
+        let mut changed = current.len() != self.static_nodes.len() + discovered.len();
+
+        for (authority, weight) in discovered {
+            let existing = current[self.static_nodes.len()..]
+                .iter()
+                .find(|node| node.authority == authority && node.weight == weight);
+            match existing {
+                Some(node) => nodes.push(node.clone()),
+                None => {
+                    let node = Arc::new(Node::new(authority, weight));
+                    if let Some(active_checker) = &self.active_checker {
+                        active_checker.spawn(&node);
+                    }
+                    nodes.push(node);
+                    changed = true;
+                }
+            }
+        }
+
+        if changed {
+            info!(
+                nodes = %nodes
+                    .iter()
+                    .map(|node| node.authority.as_str())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                "upstream nodes updated."
+            );
+        }
+        *self.nodes.write() = Arc::new(nodes);
+    }
+
+    /// Spawns a task that replaces the discovered nodes with the items of
+    /// `stream`, the task is aborted when the nodes are dropped.
+    pub fn watch(self: &Arc<Self>, mut stream: BoxStream<'static, Vec<DiscoveredNode>>) {
+        let nodes = Arc::downgrade(self);
+        self.add_task(tokio::spawn(async move {
+            while let Some(discovered) = stream.next().await {
+                let nodes = match nodes.upgrade() {
+                    Some(nodes) => nodes,
+                    None => break,
+                };
+                nodes.update(
+                    discovered
+                        .into_iter()
+                        .filter_map(|node| match node.host.parse::<Authority>() {
+                            Ok(authority) => Some((authority, node.weight.max(1))),
+                            Err(err) => {
+                                warn!(host = %node.host, error = %err, "invalid discovered node.");
+                                None
+                            }
+                        })
+                        .collect(),
+                );
+            }
+        }));
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    io::Cursor,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use poem::{
+    http::{Method, StatusCode},
+    Body,
+};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeoutConfig {
+    /// Timeout of each attempt, until the response headers are received.
+    #[serde(default)]
+    per_try_ms: Option<u64>,
+    /// Timeout of the whole request including retries.
+    #[serde(default)]
+    total_ms: Option<u64>,
+}
+
+impl TimeoutConfig {
+    pub fn per_try(&self) -> Option<Duration> {
+        self.per_try_ms.map(Duration::from_millis)
+    }
+
+    pub fn total(&self) -> Option<Duration> {
+        self.total_ms.map(Duration::from_millis)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetryConfig {
+    #[serde(default = "default_max_attempts")]
+    max_attempts: u32,
+    #[serde(default = "default_on_connect_error")]
+    on_connect_error: bool,
+    #[serde(default)]
+    on_timeout: bool,
+    #[serde(default)]
+    on_status_codes: Vec<u16>,
+    #[serde(default = "default_backoff_ms")]
+    backoff_ms: u64,
+    #[serde(default = "default_max_backoff_ms")]
+    max_backoff_ms: u64,
+    #[serde(default)]
+    non_idempotent: bool,
+    #[serde(default = "default_max_body_size")]
+    max_body_size: usize,
+}
+
+const fn default_max_attempts() -> u32 {
+    3
+}
+
+const fn default_on_connect_error() -> bool {
+    true
+}
+
+const fn default_backoff_ms() -> u64 {
+    25
+}
+
+const fn default_max_backoff_ms() -> u64 {
+    1000
+}
+
+const fn default_max_body_size() -> usize {
+    64 * 1024
+}
+
+impl RetryConfig {
+    pub fn create(&self) -> Result<RetryPolicy> {
+        anyhow::ensure!(
+            self.max_attempts > 0,
+            "`maxAttempts` must be greater than 0"
+        );
+
+        let mut status_codes = Vec::new();
+        for code in &self.on_status_codes {
+            status_codes.push(
+                StatusCode::try_from(*code)
+                    .with_context(|| format!("invalid retry status code `{}`", code))?,
+            );
+        }
+
+        Ok(RetryPolicy {
+            max_attempts: self.max_attempts,
+            on_connect_error: self.on_connect_error,
+            on_timeout: self.on_timeout,
+            status_codes,
+            backoff: Duration::from_millis(self.backoff_ms),
+            max_backoff: Duration::from_millis(self.max_backoff_ms),
+            non_idempotent: self.non_idempotent,
+            max_body_size: self.max_body_size,
+        })
+    }
+}
+
+pub enum AttemptError {
+    Connect(anyhow::Error),
+    Timeout,
+    Other(anyhow::Error),
+}
+
+impl From<reqwest::Error> for AttemptError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_connect() {
+            AttemptError::Connect(err.into())
+        } else if err.is_timeout() {
+            AttemptError::Timeout
+        } else {
+            AttemptError::Other(err.into())
+        }
+    }
+}
+
+impl Display for AttemptError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AttemptError::Connect(err) | AttemptError::Other(err) => err.fmt(f),
+            AttemptError::Timeout => f.write_str("timed out"),
+        }
+    }
+}
+
+pub struct RetryPolicy {
+    max_attempts: u32,
+    on_connect_error: bool,
+    on_timeout: bool,
+    status_codes: Vec<StatusCode>,
+    backoff: Duration,
+    max_backoff: Duration,
+    non_idempotent: bool,
+    max_body_size: usize,
+}
+
+impl RetryPolicy {
+    /// Returns the maximum number of attempts for a request with `method`.
+    pub fn max_attempts(&self, method: &Method) -> u32 {
+        let idempotent = matches!(
+            *method,
+            Method::GET
+                | Method::HEAD
+                | Method::OPTIONS
+                | Method::TRACE
+                | Method::PUT
+                | Method::DELETE
+        );
+        if idempotent || self.non_idempotent {
+            self.max_attempts
+        } else {
+            1
+        }
+    }
+
+    #[inline]
+    pub fn max_body_size(&self) -> usize {
+        self.max_body_size
+    }
+
+    pub fn should_retry_error(&self, err: &AttemptError) -> bool {
+        match err {
+            AttemptError::Connect(_) => self.on_connect_error,
+            AttemptError::Timeout => self.on_timeout,
+            AttemptError::Other(_) => false,
+        }
+    }
+
+    pub fn should_retry_status(&self, status: StatusCode) -> bool {
+        self.status_codes.contains(&status)
+    }
+
+    /// Returns the delay before the next attempt, `attempt` starts at 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.backoff
+            .checked_mul(1 << (attempt - 1).min(16))
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+/// The request body that is sent to the upstream.
+pub enum ReplayBody {
+    /// The body is buffered and can be sent multiple times.
+    Buffered(Bytes),
+    /// The body is too large to be buffered and can be sent only once.
+    Stream(Option<Body>),
+}
+
+impl ReplayBody {
+    /// Buffers the body if it is not larger than `limit`.
+    pub async fn new(body: Body, limit: usize) -> std::io::Result<Self> {
+        let mut reader = body.into_async_read();
+        let mut data = Vec::new();
+        (&mut reader)
+            .take(limit as u64 + 1)
+            .read_to_end(&mut data)
+            .await?;
+
+        if data.len() <= limit {
+            Ok(ReplayBody::Buffered(data.into()))
+        } else {
+            Ok(ReplayBody::Stream(Some(Body::from_async_read(
+                Cursor::new(data).chain(reader),
+            ))))
+        }
+    }
+
+    #[inline]
+    pub fn is_replayable(&self) -> bool {
+        matches!(self, ReplayBody::Buffered(_))
+    }
+
+    pub fn take(&mut self) -> hyper::Body {
+        match self {
+            ReplayBody::Buffered(data) => data.clone().into(),
+            ReplayBody::Stream(body) => body.take().unwrap_or_else(Body::empty).into(),
+        }
+    }
+}
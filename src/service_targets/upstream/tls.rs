@@ -0,0 +1,194 @@
+use std::{io::Cursor, sync::Arc};
+
+use anyhow::{Context, Result};
+use reqwest::{Certificate, ClientBuilder, Identity};
+use rustls::{
+    internal::pemfile, Certificate as RustlsCertificate, RootCertStore, ServerCertVerified,
+    ServerCertVerifier, TLSError,
+};
+use serde::{Deserialize, Serialize};
+use webpki::DNSNameRef;
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsConfig {
+    /// PEM bundle of the CA certificates to trust.
+    #[serde(default)]
+    ca_file: Option<String>,
+    /// Whether to trust the built-in root certificates as well.
+    #[serde(default = "default_builtin_roots")]
+    builtin_roots: bool,
+    /// PEM file of the client certificate chain for mutual TLS.
+    #[serde(default)]
+    client_cert_file: Option<String>,
+    /// PEM file of the private key of the client certificate.
+    #[serde(default)]
+    client_key_file: Option<String>,
+    /// The name used for SNI and certificate verification instead of the
+    /// host of the upstream node.
+    #[serde(default)]
+    server_name: Option<String>,
+    #[serde(default)]
+    insecure_skip_verify: bool,
+}
+
+const fn default_builtin_roots() -> bool {
+    true
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("failed to read file `{}`", path))
+}
+
+impl TlsConfig {
+    pub fn has_server_name(&self) -> bool {
+        self.server_name.is_some()
+    }
+
+    pub fn load(&self) -> Result<Tls> {
+        let ca_pem = match &self.ca_file {
+            Some(path) => Some(read_file(path)?),
+            None => None,
+        };
+        let ca = match &ca_pem {
+            Some(pem) => Some(Certificate::from_pem(pem).with_context(|| {
+                format!(
+                    "invalid CA certificates `{}`",
+                    self.ca_file.as_deref().unwrap_or_default()
+                )
+            })?),
+            None => None,
+        };
+
+        let (identity, identity_pem) = match (&self.client_cert_file, &self.client_key_file) {
+            (Some(cert_path), Some(key_path)) => {
+                let cert_pem = read_file(cert_path)?;
+                let key_pem = read_file(key_path)?;
+                let mut pem = key_pem.clone();
+                pem.push(b'\n');
+                pem.extend_from_slice(&cert_pem);
+                let identity = Identity::from_pem(&pem).with_context(|| {
+                    format!(
+                        "invalid client certificate `{}` or key `{}`",
+                        cert_path, key_path
+                    )
+                })?;
+                (Some(identity), Some((cert_pem, key_pem)))
+            }
+            (None, None) => (None, None),
+            _ => bail!("`clientCertFile` and `clientKeyFile` must be specified together"),
+        };
+
+        if self.insecure_skip_verify {
+            warn!(
+                "the upstream certificate verification is disabled, do not use it in production."
+            );
+        }
+
+        Ok(Tls {
+            ca,
+            ca_pem,
+            builtin_roots: self.builtin_roots,
+            identity,
+            identity_pem,
+            server_name: self.server_name.clone(),
+            insecure_skip_verify: self.insecure_skip_verify,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Tls {
+    ca: Option<Certificate>,
+    ca_pem: Option<Vec<u8>>,
+    builtin_roots: bool,
+    identity: Option<Identity>,
+    identity_pem: Option<(Vec<u8>, Vec<u8>)>,
+    server_name: Option<String>,
+    insecure_skip_verify: bool,
+}
+
+impl Tls {
+    #[inline]
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    pub fn apply(&self, mut builder: ClientBuilder) -> ClientBuilder {
+        builder = builder
+            .use_rustls_tls()
+            .tls_built_in_root_certs(self.builtin_roots);
+        if let Some(ca) = &self.ca {
+            builder = builder.add_root_certificate(ca.clone());
+        }
+        if let Some(identity) = &self.identity {
+            builder = builder.identity(identity.clone());
+        }
+        if self.insecure_skip_verify {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        builder
+    }
+}
+
+impl Tls {
+    /// Creates a rustls config for the connections that are not made by the
+    /// http client.
+    pub fn rustls_config(&self, alpn_protocols: &[&[u8]]) -> Result<rustls::ClientConfig> {
+        let mut config = rustls::ClientConfig::new();
+
+        if self.builtin_roots {
+            config
+                .root_store
+                .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        }
+        if let Some(pem) = &self.ca_pem {
+            config
+                .root_store
+                .add_pem_file(&mut Cursor::new(pem))
+                .map_err(|_| anyhow!("invalid CA certificates"))?;
+        }
+        if let Some((cert_pem, key_pem)) = &self.identity_pem {
+            let certs = pemfile::certs(&mut Cursor::new(cert_pem))
+                .map_err(|_| anyhow!("invalid client certificate"))?;
+            let mut keys = pemfile::pkcs8_private_keys(&mut Cursor::new(key_pem))
+                .map_err(|_| anyhow!("invalid client key"))?;
+            if keys.is_empty() {
+                keys = pemfile::rsa_private_keys(&mut Cursor::new(key_pem))
+                    .map_err(|_| anyhow!("invalid client key"))?;
+            }
+            let key = keys
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("no private key found in the client key file"))?;
+            config.set_single_client_cert(certs, key)?;
+        }
+        if self.insecure_skip_verify {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(NoVerifier));
+        }
+        config.set_protocols(
+            &alpn_protocols
+                .iter()
+                .map(|protocol| protocol.to_vec())
+                .collect::<Vec<_>>(),
+        );
+
+        Ok(config)
+    }
+}
+
+struct NoVerifier;
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        _presented_certs: &[RustlsCertificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
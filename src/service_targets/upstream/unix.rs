@@ -0,0 +1,188 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use futures_util::FutureExt;
+use hyper::client::conn::SendRequest;
+use parking_lot::Mutex;
+use poem::{
+    http::{header, StatusCode, Uri, Version},
+    Endpoint, Request, Response,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{net::UnixStream, time::error::Elapsed};
+
+use crate::{
+    config::ServiceTargetConfig,
+    plugins::StreamingBody,
+    service_targets::upstream::headers::{
+        remove_hop_by_hop_headers, ForwardedConfig, HeaderPolicy,
+    },
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UnixConfig {
+    /// The path of the Unix domain socket.
+    path: PathBuf,
+    #[serde(default)]
+    connect_timeout_ms: Option<u64>,
+    /// Timeout of waiting for the response headers.
+    #[serde(default)]
+    read_timeout_ms: Option<u64>,
+    #[serde(default = "default_pass_host")]
+    pass_host: bool,
+    #[serde(default)]
+    rewrite_host: Option<String>,
+    #[serde(default)]
+    forwarded: ForwardedConfig,
+    /// The maximum number of connections kept for the later requests.
+    #[serde(default = "default_max_idle_connections")]
+    max_idle_connections: usize,
+}
+
+const fn default_pass_host() -> bool {
+    true
+}
+
+const fn default_max_idle_connections() -> usize {
+    8
+}
+
+#[typetag::serde(name = "unix")]
+impl ServiceTargetConfig for UnixConfig {
+    fn create(&self) -> Result<Arc<dyn Endpoint<Output = Response>>> {
+        Ok(Arc::new(UnixUpstream {
+            path: self.path.clone(),
+            connect_timeout: self.connect_timeout_ms.map(Duration::from_millis),
+            read_timeout: self.read_timeout_ms.map(Duration::from_millis),
+            header_policy: HeaderPolicy::new(
+                self.pass_host,
+                self.rewrite_host.as_deref(),
+                &self.forwarded,
+            )?,
+            max_idle_connections: self.max_idle_connections,
+            connections: Default::default(),
+        }))
+    }
+}
+
+struct UnixUpstream {
+    path: PathBuf,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    header_policy: HeaderPolicy,
+    max_idle_connections: usize,
+    /// The connections are reused once their previous responses are read.
+    connections: Mutex<Vec<SendRequest<hyper::Body>>>,
+}
+
+/// Runs `fut` with the optional `timeout`.
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    fut: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, fut)
+            .await
+            .map_err(anyhow::Error::from)?,
+        None => fut.await,
+    }
+}
+
+impl UnixUpstream {
+    /// Returns a connection that is ready for a new request, or `None` if all
+    /// the connections are busy.
+    fn take_connection(&self) -> Option<SendRequest<hyper::Body>> {
+        let mut connections = self.connections.lock();
+        let mut index = 0;
+        while index < connections.len() {
+            let sender = &mut connections[index];
+            match futures_util::future::poll_fn(|cx| sender.poll_ready(cx)).now_or_never() {
+                Some(Ok(())) => return Some(connections.swap_remove(index)),
+                // the connection is closed
+                Some(Err(_)) => {
+                    connections.swap_remove(index);
+                }
+                None => index += 1,
+            }
+        }
+        None
+    }
+
+    fn put_connection(&self, sender: SendRequest<hyper::Body>) {
+        let mut connections = self.connections.lock();
+        if connections.len() < self.max_idle_connections {
+            connections.push(sender);
+        }
+    }
+
+    async fn connect(&self) -> Result<SendRequest<hyper::Body>> {
+        let stream = with_timeout(self.connect_timeout, async {
+            Ok(UnixStream::connect(&self.path).await?)
+        })
+        .await?;
+        let (sender, conn) = hyper::client::conn::handshake(stream).await?;
+        tokio::spawn(async move {
+            if let Err(err) = conn.await {
+                debug!(error = %err, "unix socket connection error.");
+            }
+        });
+        Ok(sender)
+    }
+
+    async fn send(&self, mut req: Request) -> Result<hyper::Response<hyper::Body>> {
+        let mut sender = match self.take_connection() {
+            Some(sender) => sender,
+            None => self.connect().await?,
+        };
+
+        let mut headers = self.header_policy.request_headers(&req);
+        if !headers.contains_key(header::HOST) {
+            headers.insert(header::HOST, header::HeaderValue::from_static("localhost"));
+        }
+        let uri = req
+            .uri()
+            .path_and_query()
+            .map(|path_and_query| path_and_query.as_str())
+            .unwrap_or("/")
+            .parse::<Uri>()?;
+        info!(path = %self.path.display(), uri = %uri, "forward to unix socket");
+
+        let mut upstream_req = hyper::Request::new(req.take_body().into());
+        *upstream_req.method_mut() = req.method().clone();
+        *upstream_req.uri_mut() = uri;
+        *upstream_req.version_mut() = Version::HTTP_11;
+        *upstream_req.headers_mut() = headers;
+
+        let resp = sender.send_request(upstream_req);
+        // the connection is ready again after the response is read
+        self.put_connection(sender);
+        with_timeout(self.read_timeout, async { Ok(resp.await?) }).await
+    }
+}
+
+#[async_trait::async_trait]
+impl Endpoint for UnixUpstream {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Self::Output {
+        match self.send(req).await {
+            Ok(resp) => {
+                let mut resp: Response = resp.into();
+                remove_hop_by_hop_headers(resp.headers_mut());
+                if !resp.headers().contains_key(header::CONTENT_LENGTH) {
+                    resp.extensions_mut().insert(StreamingBody);
+                }
+                resp
+            }
+            Err(err) => {
+                error!(path = %self.path.display(), error = %err, "unix socket upstream error");
+                if err.is::<Elapsed>() {
+                    StatusCode::GATEWAY_TIMEOUT.into()
+                } else {
+                    StatusCode::BAD_GATEWAY.into()
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use std::{sync::Arc, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use poem::{
+    http::{header, HeaderValue, Method, StatusCode, Uri},
+    web::{
+        websocket::{Message, WebSocket},
+        RequestBody,
+    },
+    FromRequest, IntoResponse, Request, Response,
+};
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+
+use crate::service_targets::upstream::{
+    client::{Alpn, Io},
+    node::{Node, NodeGuard},
+    Upstream,
+};
+
+/// The headers of the handshake that are generated by the upstream client.
+const HANDSHAKE_HEADERS: &[header::HeaderName] = &[
+    header::HOST,
+    header::SEC_WEBSOCKET_KEY,
+    header::SEC_WEBSOCKET_VERSION,
+    header::SEC_WEBSOCKET_EXTENSIONS,
+    header::SEC_WEBSOCKET_ACCEPT,
+];
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebSocketConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Closes the connections without any messages in either direction for
+    /// this many seconds.
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout: u64,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            idle_timeout: default_idle_timeout(),
+        }
+    }
+}
+
+const fn default_enabled() -> bool {
+    true
+}
+
+const fn default_idle_timeout() -> u64 {
+    300
+}
+
+fn has_token(req: &Request, name: header::HeaderName, token: &str) -> bool {
+    req.headers()
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+pub fn is_upgrade_request(req: &Request) -> bool {
+    req.method() == Method::GET
+        && has_token(req, header::CONNECTION, "upgrade")
+        && has_token(req, header::UPGRADE, "websocket")
+}
+
+impl Upstream {
+    pub(crate) async fn proxy_websocket(&self, mut req: Request) -> Response {
+        // poem only accepts the exact values of these headers
+        req.headers_mut()
+            .insert(header::CONNECTION, HeaderValue::from_static("Upgrade"));
+        req.headers_mut()
+            .insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+
+        let mut websocket = match WebSocket::from_request(&req, &mut RequestBody::default()).await {
+            Ok(websocket) => websocket,
+            Err(err) => return poem::Error::from(err).into_response(),
+        };
+
+        let node = match self.select_node(&req, &[]) {
+            Some(node) => node,
+            None => return StatusCode::SERVICE_UNAVAILABLE.into(),
+        };
+        let guard = node.acquire();
+
+        let (upstream, protocol) = match self.connect_websocket(&node, &req).await {
+            Ok(res) => {
+                self.report(&node, Some(StatusCode::SWITCHING_PROTOCOLS.as_u16()));
+                res
+            }
+            Err(tungstenite::Error::Http(resp)) => {
+                self.report(&node, Some(resp.status().as_u16()));
+                warn!(
+                    node = %node.authority,
+                    status = %resp.status(),
+                    "upstream rejected the websocket handshake.",
+                );
+                return resp.status().into();
+            }
+            Err(err) => {
+                self.report(&node, None);
+                error!(node = %node.authority, error = %err, "upstream websocket error");
+                return StatusCode::BAD_GATEWAY.into();
+            }
+        };
+
+        if let Some(protocol) = protocol {
+            websocket = websocket.protocols(vec![protocol]);
+        }
+
+        let idle_timeout = self.websocket_idle_timeout;
+        websocket
+            .on_upgrade(move |socket| pipe(socket, upstream, idle_timeout, node, guard))
+            .into_response()
+    }
+
+    async fn connect_websocket(
+        &self,
+        node: &Arc<Node>,
+        req: &Request,
+    ) -> Result<
+        (
+            tokio_tungstenite::WebSocketStream<Box<dyn Io>>,
+            Option<String>,
+        ),
+        tungstenite::Error,
+    > {
+        let stream = self
+            .client
+            .connect(node, Alpn::Http1)
+            .await
+            .map_err(|err| {
+                tungstenite::Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionRefused,
+                    err.to_string(),
+                ))
+            })?;
+
+        let mut headers = self.header_policy.request_headers(req);
+        let host = headers
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .map(ToString::to_string)
+            .unwrap_or_else(|| node.authority.to_string());
+        for name in HANDSHAKE_HEADERS {
+            headers.remove(name);
+        }
+
+        let path = req
+            .uri()
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or("/");
+        let uri: Uri = format!("ws://{}{}", host, path)
+            .parse()
+            .map_err(|_| tungstenite::Error::Url(tungstenite::error::UrlError::NoHostName))?;
+        let mut upstream_req = uri.into_client_request()?;
+        *upstream_req.headers_mut() = headers;
+
+        info!(node = %node.authority, uri = %req.uri(), "forward websocket to upstream");
+
+        let (upstream, resp) = tokio_tungstenite::client_async(upstream_req, stream).await?;
+        let protocol = resp
+            .headers()
+            .get(header::SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string);
+        Ok((upstream, protocol))
+    }
+}
+
+async fn pipe(
+    client: poem::web::websocket::WebSocketStream,
+    upstream: tokio_tungstenite::WebSocketStream<Box<dyn Io>>,
+    idle_timeout: Duration,
+    node: Arc<Node>,
+    _guard: NodeGuard,
+) {
+    let (mut client_sink, mut client_stream) = client.split();
+    let (mut upstream_sink, mut upstream_stream) = upstream.split();
+
+    loop {
+        let res = tokio::time::timeout(idle_timeout, async {
+            tokio::select! {
+                msg = client_stream.next() => match msg {
+                    Some(Ok(msg)) => {
+                        let is_close = msg.is_close();
+                        upstream_sink.send(msg.into()).await.map_err(|err| err.to_string())?;
+                        Ok(!is_close)
+                    }
+                    Some(Err(err)) => Err(err.to_string()),
+                    None => Ok(false),
+                },
+                msg = upstream_stream.next() => match msg {
+                    Some(Ok(msg)) => {
+                        let msg: Message = msg.into();
+                        let is_close = msg.is_close();
+                        client_sink.send(msg).await.map_err(|err| err.to_string())?;
+                        Ok(!is_close)
+                    }
+                    Some(Err(err)) => Err(err.to_string()),
+                    None => Ok(false),
+                },
+            }
+        })
+        .await;
+
+        match res {
+            Ok(Ok(true)) => {}
+            Ok(Ok(false)) => break,
+            Ok(Err(err)) => {
+                debug!(node = %node.authority, error = %err, "websocket connection error.");
+                break;
+            }
+            Err(_) => {
+                debug!(node = %node.authority, "websocket connection is idle, close it.");
+                let _ = client_sink.send(Message::close()).await;
+                let _ = upstream_sink.send(tungstenite::Message::Close(None)).await;
+                break;
+            }
+        }
+    }
+
+    let _ = client_sink.close().await;
+    let _ = upstream_sink.close().await;
+}
@@ -0,0 +1,168 @@
+mod tcp;
+mod udp;
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use poem::{web::RemoteAddr, Request};
+use tokio::{
+    net::{TcpListener, UdpSocket},
+    time::Instant,
+};
+
+use crate::{
+    config::{StreamProtocol, StreamRouteConfig},
+    consumer_filters::ConsumerFilter,
+    service_targets::upstream::{
+        balancer::Balancer,
+        node::{Node, NodeList},
+    },
+};
+
+enum Listener {
+    Tcp(TcpListener),
+    Udp(UdpSocket),
+}
+
+pub struct StreamRoute {
+    listener: Listener,
+    proxy: Arc<StreamProxy>,
+}
+
+impl StreamRoute {
+    pub async fn bind(config: &StreamRouteConfig) -> Result<Self> {
+        let mut nodes = Vec::new();
+        for node in &config.nodes {
+            let node = node.create()?;
+            anyhow::ensure!(
+                node.authority.port_u16().is_some(),
+                "the port of node `{}` is required",
+                node.authority
+            );
+            nodes.push(node);
+        }
+        anyhow::ensure!(
+            !nodes.is_empty() || config.dns.is_some() || config.discovery.is_some(),
+            "at least one upstream node is required"
+        );
+        anyhow::ensure!(
+            config.dns.is_none() || config.discovery.is_none(),
+            "`dns` and `discovery` cannot be used together"
+        );
+        anyhow::ensure!(
+            config.idle_timeout > 0,
+            "the idle timeout must be greater than 0"
+        );
+
+        let mut filters = Vec::new();
+        for filter in &config.filters {
+            filters.push(filter.create()?);
+        }
+
+        let nodes = Arc::new(NodeList::new(nodes, None));
+        if let Some(dns) = &config.dns {
+            dns.spawn(None, &nodes)?;
+        }
+        if let Some(discovery) = &config.discovery {
+            nodes.watch(discovery.watch()?);
+        }
+
+        let listener = match config.protocol {
+            StreamProtocol::Tcp => Listener::Tcp(
+                TcpListener::bind(&config.bind)
+                    .await
+                    .with_context(|| format!("failed to bind `{}`", config.bind))?,
+            ),
+            StreamProtocol::Udp => Listener::Udp(
+                UdpSocket::bind(&config.bind)
+                    .await
+                    .with_context(|| format!("failed to bind `{}`", config.bind))?,
+            ),
+        };
+
+        Ok(Self {
+            listener,
+            proxy: Arc::new(StreamProxy {
+                name: config.name.clone(),
+                filters,
+                nodes,
+                balancer: config.balancer.create()?,
+                connect_timeout: config.connect_timeout_ms.map(Duration::from_millis),
+                connect_attempts: config.connect_attempts.max(1),
+                idle_timeout: Duration::from_secs(config.idle_timeout),
+                max_sessions: config.max_sessions,
+            }),
+        })
+    }
+
+    pub async fn run(self) {
+        match self.listener {
+            Listener::Tcp(listener) => self.proxy.run_tcp(listener).await,
+            Listener::Udp(socket) => self.proxy.run_udp(Arc::new(socket)).await,
+        }
+    }
+}
+
+/// The state shared by the connections of a stream route.
+struct StreamProxy {
+    name: String,
+    filters: Vec<Arc<dyn ConsumerFilter>>,
+    nodes: Arc<NodeList>,
+    balancer: Box<dyn Balancer>,
+    connect_timeout: Option<Duration>,
+    connect_attempts: usize,
+    idle_timeout: Duration,
+    max_sessions: usize,
+}
+
+impl StreamProxy {
+    /// Returns a request that carries the address of the client, so that the
+    /// consumer filters and the balancers can be applied to the streams.
+    fn make_request(addr: SocketAddr) -> Request {
+        Request::from((
+            poem::http::Request::new(hyper::Body::empty()),
+            RemoteAddr::SocketAddr(addr),
+        ))
+    }
+
+    fn check_client(&self, req: &Request) -> bool {
+        self.filters.iter().all(|filter| filter.check(req))
+    }
+}
+
+/// Returns the address of `node`, resolving its host if needed.
+async fn resolve_node(node: &Node) -> Result<SocketAddr> {
+    let port = node
+        .authority
+        .port_u16()
+        .ok_or_else(|| anyhow!("the port of node `{}` is required", node.authority))?;
+    tokio::net::lookup_host((node.host(), port))
+        .await?
+        .next()
+        .ok_or_else(|| anyhow!("failed to resolve `{}`", node.authority))
+}
+
+/// The time of the last traffic of a connection or session.
+struct Activity(Mutex<Instant>);
+
+impl Activity {
+    fn new() -> Self {
+        Self(Mutex::new(Instant::now()))
+    }
+
+    fn touch(&self) {
+        *self.0.lock() = Instant::now();
+    }
+
+    /// Completes when there is no traffic for `idle_timeout`.
+    async fn idle(&self, idle_timeout: Duration) {
+        loop {
+            let deadline = *self.0.lock() + idle_timeout;
+            if Instant::now() >= deadline {
+                return;
+            }
+            tokio::time::sleep_until(deadline).await;
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    service_targets::upstream::node::Node,
+    stream_routes::{resolve_node, Activity, StreamProxy},
+};
+
+async fn copy(
+    mut reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    activity: &Activity,
+) -> std::io::Result<()> {
+    let mut buf = vec![0; 16 * 1024];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return writer.shutdown().await;
+        }
+        activity.touch();
+        writer.write_all(&buf[..n]).await?;
+    }
+}
+
+impl StreamProxy {
+    pub(super) async fn run_tcp(self: Arc<Self>, listener: TcpListener) {
+        info!(name = %self.name, addr = ?listener.local_addr().ok(), "stream route listening.");
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) => {
+                    tokio::spawn(self.clone().handle_tcp(stream, addr));
+                }
+                Err(err) => {
+                    warn!(name = %self.name, error = %err, "failed to accept the connection.");
+                }
+            }
+        }
+    }
+
+    async fn connect_tcp(&self, node: &Node) -> Result<TcpStream> {
+        let connect =
+            async { Ok::<_, anyhow::Error>(TcpStream::connect(resolve_node(node).await?).await?) };
+        match self.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connect)
+                .await
+                .map_err(|_| anyhow!("connect to `{}` timed out", node.authority))?,
+            None => connect.await,
+        }
+    }
+
+    async fn handle_tcp(self: Arc<Self>, mut stream: TcpStream, addr: SocketAddr) {
+        let req = Self::make_request(addr);
+        if !self.check_client(&req) {
+            debug!(name = %self.name, client = %addr, "the client is not allowed.");
+            return;
+        }
+
+        let mut tried = Vec::new();
+        let (node, mut upstream) = loop {
+            let node = match self.nodes.select(&*self.balancer, &req, &tried) {
+                Some(node) => node,
+                None => return,
+            };
+            match self.connect_tcp(&node).await {
+                Ok(upstream) => break (node, upstream),
+                Err(err) => {
+                    warn!(
+                        name = %self.name,
+                        node = %node.authority,
+                        error = %err,
+                        "failed to connect to the upstream node.",
+                    );
+                    tried.push(node);
+                    if tried.len() >= self.connect_attempts {
+                        return;
+                    }
+                }
+            }
+        };
+
+        let _guard = node.acquire();
+        debug!(name = %self.name, client = %addr, node = %node.authority, "proxy the connection.");
+
+        let activity = Activity::new();
+        let (client_reader, client_writer) = stream.split();
+        let (upstream_reader, upstream_writer) = upstream.split();
+        let pipe = futures_util::future::try_join(
+            copy(client_reader, upstream_writer, &activity),
+            copy(upstream_reader, client_writer, &activity),
+        );
+
+        tokio::select! {
+            res = pipe => {
+                if let Err(err) = res {
+                    debug!(name = %self.name, client = %addr, error = %err, "connection closed.");
+                }
+            }
+            _ = activity.idle(self.idle_timeout) => {
+                debug!(name = %self.name, client = %addr, "connection idle timed out.");
+            }
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use tokio::net::UdpSocket;
+
+use crate::{
+    service_targets::upstream::node::{Node, NodeGuard},
+    stream_routes::{resolve_node, Activity, StreamProxy},
+};
+
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+/// The number of datagrams kept while the session is being created, the
+/// rest are dropped.
+const MAX_PENDING_DATAGRAMS: usize = 16;
+
+/// The datagrams of a client are sent to the same node until the session is
+/// idle.
+struct UdpSession {
+    socket: UdpSocket,
+    activity: Activity,
+    _guard: NodeGuard,
+}
+
+enum SessionState {
+    /// The session is being created, with the datagrams received meanwhile.
+    Pending(Vec<Vec<u8>>),
+    Ready(Arc<UdpSession>),
+}
+
+type Sessions = Arc<Mutex<HashMap<SocketAddr, SessionState>>>;
+
+impl StreamProxy {
+    pub(super) async fn run_udp(self: Arc<Self>, socket: Arc<UdpSocket>) {
+        info!(name = %self.name, addr = ?socket.local_addr().ok(), "stream route listening.");
+
+        let sessions: Sessions = Default::default();
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+
+        loop {
+            let (n, addr) = match socket.recv_from(&mut buf).await {
+                Ok(res) => res,
+                Err(err) => {
+                    warn!(name = %self.name, error = %err, "failed to receive the datagram.");
+                    continue;
+                }
+            };
+
+            let session = {
+                let mut guard = sessions.lock();
+                match guard.get_mut(&addr) {
+                    Some(SessionState::Ready(session)) => session.clone(),
+                    Some(SessionState::Pending(datagrams)) => {
+                        if datagrams.len() < MAX_PENDING_DATAGRAMS {
+                            datagrams.push(buf[..n].to_vec());
+                        }
+                        continue;
+                    }
+                    None => {
+                        if guard.len() >= self.max_sessions {
+                            debug!(
+                                name = %self.name,
+                                client = %addr,
+                                "too many udp sessions, the datagram is dropped.",
+                            );
+                            continue;
+                        }
+                        let req = Self::make_request(addr);
+                        if !self.check_client(&req) {
+                            debug!(name = %self.name, client = %addr, "the client is not allowed.");
+                            continue;
+                        }
+                        let node = match self.nodes.select(&*self.balancer, &req, &[]) {
+                            Some(node) => node,
+                            None => continue,
+                        };
+
+                        // the session is created in the background, so that the
+                        // other clients are not blocked by resolving the node
+                        let datagrams = vec![buf[..n].to_vec()];
+                        guard.insert(addr, SessionState::Pending(datagrams));
+                        tokio::spawn(self.clone().run_udp_session(
+                            addr,
+                            node,
+                            socket.clone(),
+                            sessions.clone(),
+                        ));
+                        continue;
+                    }
+                }
+            };
+
+            self.forward_datagram(&session, addr, &buf[..n]).await;
+        }
+    }
+
+    async fn forward_datagram(&self, session: &UdpSession, addr: SocketAddr, data: &[u8]) {
+        session.activity.touch();
+        if let Err(err) = session.socket.send(data).await {
+            debug!(
+                name = %self.name,
+                client = %addr,
+                error = %err,
+                "failed to send the datagram.",
+            );
+        }
+    }
+
+    async fn bind_udp(&self, node: &Node) -> Result<UdpSocket> {
+        let addr = resolve_node(node).await?;
+        let local_addr: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local_addr).await?;
+        socket.connect(addr).await?;
+        Ok(socket)
+    }
+
+    /// Creates the session of the client and sends the responses of the node
+    /// back until the session is idle.
+    async fn run_udp_session(
+        self: Arc<Self>,
+        addr: SocketAddr,
+        node: Arc<Node>,
+        listener: Arc<UdpSocket>,
+        sessions: Sessions,
+    ) {
+        let socket = match self.bind_udp(&node).await {
+            Ok(socket) => socket,
+            Err(err) => {
+                warn!(
+                    name = %self.name,
+                    node = %node.authority,
+                    error = %err,
+                    "failed to connect to the upstream node.",
+                );
+                sessions.lock().remove(&addr);
+                return;
+            }
+        };
+        debug!(
+            name = %self.name,
+            client = %addr,
+            node = %node.authority,
+            "create the udp session.",
+        );
+
+        let session = Arc::new(UdpSession {
+            socket,
+            activity: Activity::new(),
+            _guard: node.acquire(),
+        });
+        let pending = sessions
+            .lock()
+            .insert(addr, SessionState::Ready(session.clone()));
+        if let Some(SessionState::Pending(datagrams)) = pending {
+            for data in datagrams {
+                self.forward_datagram(&session, addr, &data).await;
+            }
+        }
+
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        loop {
+            tokio::select! {
+                res = session.socket.recv(&mut buf) => match res {
+                    Ok(n) => {
+                        session.activity.touch();
+                        if let Err(err) = listener.send_to(&buf[..n], addr).await {
+                            debug!(
+                                name = %self.name,
+                                client = %addr,
+                                error = %err,
+                                "failed to send the datagram.",
+                            );
+                        }
+                    }
+                    Err(err) => {
+                        debug!(
+                            name = %self.name,
+                            client = %addr,
+                            error = %err,
+                            "failed to receive the datagram.",
+                        );
+                        break;
+                    }
+                },
+                _ = session.activity.idle(self.idle_timeout) => break,
+            }
+        }
+        debug!(name = %self.name, client = %addr, "the udp session is closed.");
+        let mut sessions = sessions.lock();
+        if matches!(
+            sessions.get(&addr),
+            Some(SessionState::Ready(current)) if Arc::ptr_eq(current, &session)
+        ) {
+            sessions.remove(&addr);
+        }
+    }
+}